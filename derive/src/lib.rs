@@ -0,0 +1,149 @@
+//! `#[derive(FromAst)]`, which implements `parser::FromAst` for structs and enums, converting
+//! trees by tag name. See the documentation of `parser::FromAst` for the conversion rules.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{
+    Attribute, Data, DeriveInput, Fields, GenericParam, Ident, LitStr, parse_macro_input,
+    parse_quote,
+};
+
+#[proc_macro_derive(FromAst, attributes(from_ast))]
+pub fn derive_from_ast(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+/// `CamelCase` to `snake_case`, the way parsers are usually named.
+fn snake_case(ident: &Ident) -> String {
+    let mut name = String::new();
+    for (i, c) in ident.to_string().chars().enumerate() {
+        if c.is_uppercase() {
+            if i > 0 {
+                name.push('_');
+            }
+            name.extend(c.to_lowercase());
+        } else {
+            name.push(c);
+        }
+    }
+    name
+}
+
+/// The tag set by `#[from_ast(tag = "...")]`, or the name of the item in snake case.
+fn tag(attributes: &[Attribute], ident: &Ident) -> syn::Result<String> {
+    let mut tag = None;
+    for attribute in attributes {
+        if !attribute.path().is_ident("from_ast") {
+            continue;
+        }
+        attribute.parse_nested_meta(|meta| {
+            if meta.path.is_ident("tag") {
+                tag = Some(meta.value()?.parse::<LitStr>()?.value());
+                Ok(())
+            } else {
+                Err(meta.error("expected `tag = \"...\"`"))
+            }
+        })?;
+    }
+    Ok(tag.unwrap_or_else(|| snake_case(ident)))
+}
+
+/// Builds `constructor` from the fields of a node tagged `tag`, an expression of type `Symbol`, the
+/// conversion of field `i` being bound to `fields[i]`.
+fn construct(constructor: TokenStream2, fields: &Fields, tag: TokenStream2) -> TokenStream2 {
+    let count = fields.len();
+    let values = (0..count).map(|i| quote!(::parser::FromAst::from_ast(fields[#i])?));
+    let value = match fields {
+        Fields::Named(named) => {
+            let names = named.named.iter().map(|field| &field.ident);
+            quote!(#constructor { #(#names: #values),* })
+        }
+        Fields::Unnamed(_) => quote!(#constructor ( #(#values),* )),
+        Fields::Unit => quote!(#constructor),
+    };
+    if count == 0 {
+        return quote! {
+            ::parser::fields_of(tree, &#tag, 0)?;
+            Ok(#value)
+        };
+    }
+    quote! {
+        let fields = ::parser::fields_of(tree, &#tag, #count)?;
+        Ok(#value)
+    }
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    // The tags are symbols built once, in `TAGS`, so that they are compared to the tags of the
+    // trees without comparing texts.
+    let mut tags = vec![];
+    let body = match &input.data {
+        Data::Struct(data) => {
+            tags.push(tag(&input.attrs, name)?);
+            construct(quote!(#name), &data.fields, quote!(TAGS[0]))
+        }
+        Data::Enum(data) => {
+            let mut arms = vec![];
+            for (i, variant) in data.variants.iter().enumerate() {
+                let ident = &variant.ident;
+                tags.push(tag(&variant.attrs, ident)?);
+                let body = construct(quote!(#name::#ident), &variant.fields, quote!(TAGS[#i]));
+                arms.push(quote!(Some(tag) if *tag == TAGS[#i] => { #body }));
+            }
+            quote! {
+                match tree.symbol() {
+                    #(#arms)*
+                    _ => Err(::parser::FromAstError::unexpected_tag(tree, &[#(#tags),*])),
+                }
+            }
+        }
+        Data::Union(_) => {
+            return Err(syn::Error::new_spanned(
+                input,
+                "FromAst cannot be derived for unions",
+            ));
+        }
+    };
+
+    // Fields are converted from leaves that are text, and type parameters from the same trees.
+    // Bounding field types instead would make the bounds of recursive types cyclic.
+    let mut generics = input.generics.clone();
+    let type_parameters: Vec<Ident> = generics
+        .type_params()
+        .map(|parameter| parameter.ident.clone())
+        .collect();
+    let where_clause = generics.make_where_clause();
+    where_clause
+        .predicates
+        .push(parse_quote!(__L: ::std::convert::AsRef<str>));
+    for parameter in type_parameters {
+        where_clause
+            .predicates
+            .push(parse_quote!(#parameter: ::parser::FromAst<__L>));
+    }
+    let count = tags.len();
+    let (_, type_generics, _) = input.generics.split_for_impl();
+    let mut impl_generics = generics.clone();
+    impl_generics
+        .params
+        .push(GenericParam::Type(parse_quote!(__L)));
+    let (impl_generics, _, where_clause) = impl_generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::parser::FromAst<__L> for #name #type_generics #where_clause {
+            fn from_ast(
+                tree: &::parser::AbstractSyntaxTree<__L>,
+            ) -> ::std::result::Result<Self, ::parser::FromAstError> {
+                static TAGS: ::std::sync::LazyLock<[::parser::Symbol; #count]> =
+                    ::std::sync::LazyLock::new(|| [#(::parser::Symbol::new(#tags)),*]);
+                #body
+            }
+        }
+    })
+}
@@ -0,0 +1,97 @@
+use parser::AbstractSyntaxTree::{Branch, Ignore, Leaf};
+use parser::{FromAst, FromAstError, Span};
+
+#[derive(FromAst, Debug, PartialEq)]
+struct Assignment {
+    name: Identifier,
+    value: i64,
+}
+
+#[derive(FromAst, Debug, PartialEq)]
+#[from_ast(tag = "name")]
+struct Identifier(String);
+
+#[derive(FromAst, Debug, PartialEq)]
+enum Literal {
+    Number(i64),
+    #[from_ast(tag = "string")]
+    Text(String),
+    Null,
+}
+
+#[test]
+fn test_struct_from_branch() {
+    let tree = Branch(
+        "assignment".into(),
+        vec![
+            Leaf("name".into(), "x", Span::new(0, 1)),
+            Ignore(Span::new(1, 2)),
+            Leaf("number".into(), "42", Span::new(2, 4)),
+        ]
+        .into(),
+        Span::new(0, 4),
+    );
+    assert_eq!(
+        Assignment::from_ast(&tree),
+        Ok(Assignment {
+            name: Identifier("x".to_string()),
+            value: 42,
+        })
+    );
+}
+
+#[test]
+fn test_enum_from_leaf() {
+    let number = Leaf("number".into(), "7", Span::new(0, 1));
+    assert_eq!(Literal::from_ast(&number), Ok(Literal::Number(7)));
+    let string = Leaf("string".into(), "seven", Span::new(0, 5));
+    assert_eq!(
+        Literal::from_ast(&string),
+        Ok(Literal::Text("seven".to_string()))
+    );
+    let null = Leaf("null".into(), "null", Span::new(0, 4));
+    assert_eq!(Literal::from_ast(&null), Ok(Literal::Null));
+}
+
+#[test]
+fn test_shape_mismatch() {
+    // One child is missing.
+    let tree = Branch(
+        "assignment".into(),
+        vec![Leaf("name".into(), "x", Span::new(0, 1))].into(),
+        Span::new(0, 1),
+    );
+    assert_eq!(
+        Assignment::from_ast(&tree),
+        Err(FromAstError {
+            message: "expected 2 children in \"assignment\", found 1".to_string(),
+            span: Span::new(0, 1),
+        })
+    );
+
+    // The leaf cannot be converted to the type of the field.
+    let tree = Branch(
+        "assignment".into(),
+        vec![
+            Leaf("name".into(), "x", Span::new(0, 1)),
+            Leaf("number".into(), "x", Span::new(2, 3)),
+        ]
+        .into(),
+        Span::new(0, 3),
+    );
+    assert_eq!(
+        Assignment::from_ast(&tree).unwrap_err().span,
+        Span::new(2, 3)
+    );
+
+    // No variant has the tag of the node.
+    let boolean = Leaf("boolean".into(), "true", Span::new(3, 7));
+    assert_eq!(
+        Literal::from_ast(&boolean),
+        Err(FromAstError {
+            message: "expected one of \"number\", \"string\", \"null\", found leaf \"boolean\""
+                .to_string(),
+            span: Span::new(3, 7),
+        })
+    );
+}
@@ -2,161 +2,253 @@ use crate::PropagatedParseError::Atomic;
 use crate::parser::AbstractSyntaxTree::{Ignore, Leaf};
 use crate::parser::*;
 use crate::utils::*;
+use crate::{Input, Symbol, describe_first_item};
+use std::borrow::Borrow;
 use std::collections::HashSet;
-use std::rc::Rc;
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::sync::Arc;
 
-pub struct StringParser {
-    name: String,
-    string: String,
+/// Parses a fixed sequence of items: a string when parsing a `str`, a sequence of bytes or tokens
+/// when parsing a slice.
+pub struct StringParser<I: Input + ?Sized = str> {
+    name: Symbol,
+    string: I::Owned,
 }
 
-impl Parser for StringParser {
-    fn parse<'a>(&self, input: &'a str) -> ParseResult<'a, AbstractSyntaxTree> {
-        let mut input_it = input.chars();
-        let mut pattern_it = self.string.chars();
-        while let Some(expected) = pattern_it.next() {
-            if let Some(actual) = input_it.next() {
+impl<I> Parser<I> for StringParser<I>
+where
+    I: Input + ?Sized,
+    I::Item: PartialEq,
+{
+    fn parse_at<'a>(
+        &self,
+        input: &'a I,
+        offset: usize,
+    ) -> ParseResult<'a, AbstractSyntaxTree<&'a I>, I> {
+        let mut remaining = input;
+        let mut pattern: &I = self.string.borrow();
+        while let Some((expected, width)) = pattern.first_item() {
+            pattern = pattern.split_input_at(width).1;
+            let position = offset + input.input_len() - remaining.input_len();
+            if let Some((actual, width)) = remaining.first_item() {
                 if actual != expected {
+                    let (actual, expected) =
+                        (I::describe_item(&actual), I::describe_item(&expected));
                     return Err(ParseError::new(
                         format!("expected \"{expected}\" but got \"{actual}\""),
                         self.name.clone(),
-                        Atomic(actual.to_string(), expected.to_string()),
+                        position,
+                        Atomic(actual, expected),
                     ));
                 }
+                remaining = remaining.split_input_at(width).1;
             } else {
+                let expected = I::describe_item(&expected);
                 return Err(ParseError::new(
                     format!("exhausted input but expected \"{expected}\""),
                     self.name.clone(),
-                    Atomic("".to_string(), expected.to_string()),
+                    position,
+                    Atomic("".to_string(), expected),
                 ));
             }
         }
-        Ok((
-            input_it.as_str(),
-            Leaf(self.name.clone(), self.string.clone()),
-        ))
+        let span = Span::consumed(input, remaining, offset);
+        let parsed = input.split_input_at(span.len()).0;
+        Ok((remaining, Leaf(self.name.clone(), parsed, span)))
     }
 
     fn get_name_clone(&self) -> String {
-        self.name.clone()
+        self.name.to_string()
     }
 }
 
 impl StringParser {
     pub fn new<T: ToString>(string: T) -> StringParser {
         StringParser {
-            name: string.to_string(),
+            name: string.to_string().into(),
             string: string.to_string(),
         }
     }
+
+    /// Like `StringParser::new`, but the leaves are tagged with `name` instead of the string itself.
+    pub fn named<N: Into<Symbol>, T: ToString>(name: N, string: T) -> StringParser {
+        StringParser {
+            name: name.into(),
+            string: string.to_string(),
+        }
+    }
+}
+
+impl<T: Clone + Debug> StringParser<[T]> {
+    /// Parser that matches `pattern` in slices of `T`, e.g. a magic number in a `[u8]`.
+    pub fn from_slice<N: Into<Symbol>>(name: N, pattern: &[T]) -> StringParser<[T]> {
+        StringParser {
+            name: name.into(),
+            string: pattern.to_vec(),
+        }
+    }
 }
 
-pub struct CharacterFromAlphabet {
-    pub(crate) name: String,
-    pub alphabet: Rc<HashSet<char>>,
+/// Parses a single item of the alphabet: a `char` when parsing a `str`, an element when parsing a slice.
+pub struct CharacterFromAlphabet<T = char> {
+    pub(crate) name: Symbol,
+    pub alphabet: Arc<HashSet<T>>,
+    pub(crate) negated: bool,
 }
 
-impl Parser for CharacterFromAlphabet {
-    fn parse<'a>(&self, input: &'a str) -> ParseResult<'a, AbstractSyntaxTree> {
-        let mut input_it = input.chars();
-        match input_it.next() {
+impl<T> CharacterFromAlphabet<T> {
+    pub fn new<N: Into<Symbol>>(name: N, alphabet: Arc<HashSet<T>>) -> CharacterFromAlphabet<T> {
+        CharacterFromAlphabet {
+            name: name.into(),
+            alphabet,
+            negated: false,
+        }
+    }
+
+    /// Makes the parser accept the items that are not in the alphabet instead, like `[^"]` in a
+    /// regular expression.
+    pub fn negated(mut self) -> CharacterFromAlphabet<T> {
+        self.negated = !self.negated;
+        self
+    }
+}
+
+impl<I> Parser<I> for CharacterFromAlphabet<I::Item>
+where
+    I: Input + ?Sized,
+    I::Item: Eq + Hash + Debug,
+{
+    fn parse_at<'a>(
+        &self,
+        input: &'a I,
+        offset: usize,
+    ) -> ParseResult<'a, AbstractSyntaxTree<&'a I>, I> {
+        // What the parser expected, as reported by `Atomic`.
+        let alphabet = || {
+            let negation = if self.negated { "not " } else { "" };
+            format!("{negation}{:?}", self.alphabet)
+        };
+        match input.first_item() {
             None => Err(ParseError::new(
                 format!(
-                    "Expected character from alphabet {:?} but found an empty input.",
-                    self.alphabet
+                    "Expected character from alphabet {} but found an empty input.",
+                    alphabet()
                 ),
                 self.name.clone(),
-                Atomic("".to_string(), format!("{:?}", self.alphabet)),
+                offset,
+                Atomic("".to_string(), alphabet()),
             )),
-            Some(c) => {
-                if self.alphabet.contains(&c) {
-                    return Ok((input_it.as_str(), Leaf(self.name.clone(), c.to_string())));
+            Some((c, width)) => {
+                if self.alphabet.contains(&c) != self.negated {
+                    let (parsed, remaining) = input.split_input_at(width);
+                    let span = Span::new(offset, offset + width);
+                    return Ok((remaining, Leaf(self.name.clone(), parsed, span)));
                 }
+                let c = I::describe_item(&c);
                 Err(ParseError::new(
                     format!(
-                        "Expected character from alphabet {:?} but found {c}.",
-                        self.alphabet
+                        "Expected character from alphabet {} but found {c}.",
+                        alphabet()
                     ),
                     self.name.clone(),
-                    Atomic(c.to_string(), format!("{:?}", self.alphabet)),
+                    offset,
+                    Atomic(c, alphabet()),
                 ))
             }
         }
     }
 
     fn get_name_clone(&self) -> String {
-        self.name.clone()
+        self.name.to_string()
     }
 }
 
 /// Parses any word over the specified alphabet.
-pub struct AllWordsFromAlphabet {
-    pub name: String,
-    pub alphabet: Rc<HashSet<char>>,
+pub struct AllWordsFromAlphabet<T = char> {
+    pub name: Symbol,
+    pub alphabet: Arc<HashSet<T>>,
     pub allow_empty_word: bool,
 }
 
-impl Parser for AllWordsFromAlphabet {
-    fn parse<'a>(&self, input: &'a str) -> ParseResult<'a, AbstractSyntaxTree> {
-        let end = input
-            .char_indices()
-            .position(|(_, c)| !self.alphabet.contains(&c));
-        match end {
-            // If all characters in the input are in the alphabet, we still need to check whether
-            // the input is empty.
-            None => {
-                if !input.is_empty() || self.allow_empty_word {
-                    Ok(("", Leaf(self.name.clone(), input.to_string())))
-                } else {
-                    Err(ParseError::new(
-                        format!(
-                            "Expected a non-empty word over alphabet {:?} but got nothing.",
-                            self.alphabet
-                        ),
-                        self.name.clone(),
-                        Atomic("".to_string(), format!("non-empty of {:?}", self.alphabet)),
-                    ))
-                }
-            }
-            Some(end) => {
-                // We split the input around the index of the first character that we cannot parse.
-                let (parsed, remaining) = input.split_at(end);
-
-                if !parsed.is_empty() || self.allow_empty_word {
-                    Ok((remaining, Leaf(self.name.clone(), parsed.to_string())))
-                } else {
-                    let actual = match remaining.chars().next() {
-                        None => "nothing".to_string(),
-                        Some(x) => x.to_string(),
-                    };
-                    Err(ParseError::new(
-                        format!(
-                            "Expected a non-empty word over alphabet {:?} but got \"{}\".",
-                            self.alphabet, actual
-                        ),
-                        self.name.clone(),
-                        Atomic(actual, format!("non-empty of {:?}", self.alphabet)),
-                    ))
-                }
+impl<I> Parser<I> for AllWordsFromAlphabet<I::Item>
+where
+    I: Input + ?Sized,
+    I::Item: Eq + Hash + Debug,
+{
+    fn parse_at<'a>(
+        &self,
+        input: &'a I,
+        offset: usize,
+    ) -> ParseResult<'a, AbstractSyntaxTree<&'a I>, I> {
+        // We look for the first item that we cannot parse, and split the input around it.
+        let mut end = 0;
+        let mut rest = input;
+        while let Some((c, width)) = rest.first_item() {
+            if !self.alphabet.contains(&c) {
+                break;
             }
+            end += width;
+            rest = rest.split_input_at(width).1;
+        }
+        let (parsed, remaining) = input.split_input_at(end);
+
+        if !parsed.is_input_empty() || self.allow_empty_word {
+            return Ok((
+                remaining,
+                Leaf(
+                    self.name.clone(),
+                    parsed,
+                    Span::consumed(input, remaining, offset),
+                ),
+            ));
+        }
+        if remaining.is_input_empty() {
+            Err(ParseError::new(
+                format!(
+                    "Expected a non-empty word over alphabet {:?} but got nothing.",
+                    self.alphabet
+                ),
+                self.name.clone(),
+                offset,
+                Atomic("".to_string(), format!("non-empty of {:?}", self.alphabet)),
+            ))
+        } else {
+            let actual = describe_first_item(remaining);
+            Err(ParseError::new(
+                format!(
+                    "Expected a non-empty word over alphabet {:?} but got \"{}\".",
+                    self.alphabet, actual
+                ),
+                self.name.clone(),
+                offset,
+                Atomic(actual, format!("non-empty of {:?}", self.alphabet)),
+            ))
         }
     }
 
     fn get_name_clone(&self) -> String {
-        self.name.clone()
+        self.name.to_string()
     }
 }
 
 pub struct EndOfInputParser;
-impl Parser for EndOfInputParser {
-    fn parse<'a>(&self, input: &'a str) -> ParseResult<'a, AbstractSyntaxTree> {
-        if input.is_empty() {
-            return Ok((input, Ignore));
+impl<I: Input + ?Sized> Parser<I> for EndOfInputParser {
+    fn parse_at<'a>(
+        &self,
+        input: &'a I,
+        offset: usize,
+    ) -> ParseResult<'a, AbstractSyntaxTree<&'a I>, I> {
+        if input.is_input_empty() {
+            return Ok((input, Ignore(Span::new(offset, offset))));
         }
+        // Only the next item is reported: the remaining input can be arbitrarily long.
+        let actual = describe_first_item(input);
         Err(ParseError::new(
-            format!("Expected end of input but got \"{}\"", input),
+            format!("Expected end of input but got \"{actual}\""),
             "end_of_input",
-            Atomic(input.to_string(), "".to_string()),
+            offset,
+            Atomic(actual, "".to_string()),
         ))
     }
 
@@ -0,0 +1,190 @@
+use crate::{AbstractSyntaxTree, Input, ParseError, ParseResult, Parser};
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Parses many inputs with the same parser on a pool of threads, for instance all the
+/// configuration files of a repository. Results are returned in the order of the inputs.
+/// The parser is shared between the threads, so it comes from a `ThreadSafe` graph, see `sync`.
+///
+/// ```
+/// # use parser::{Batch, sync::Grammar};
+/// let grammar = Grammar::from_peg("list = [0-9]+ (~\",\" [0-9]+)* ;").unwrap();
+/// let batch = Batch::new(grammar.get("list").unwrap()).threads(2);
+/// let (results, statistics) = batch.parse(&["1,2", "3", "4;", "x"]);
+/// assert!(results[0].is_ok() && results[3].is_err());
+/// assert_eq!((statistics.accepted, statistics.partial, statistics.rejected), (2, 1, 1));
+/// ```
+pub struct Batch<I: Input + ?Sized = str> {
+    parser: Arc<dyn Parser<I> + Send + Sync>,
+    threads: usize,
+}
+
+/// Aggregate statistics of a `Batch` run.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct BatchStatistics {
+    /// Number of inputs given to the batch.
+    pub inputs: usize,
+
+    /// Number of inputs that were parsed entirely.
+    pub accepted: usize,
+
+    /// Number of inputs of which the parser accepted a prefix only, leaving trailing input.
+    pub partial: usize,
+    pub rejected: usize,
+
+    /// Number of files that could not be read, see `Batch::parse_files`.
+    pub unreadable: usize,
+
+    /// Total length of the inputs that were parsed, in units of the input (bytes for `str`).
+    pub units: usize,
+
+    /// Number of threads that parsed the inputs.
+    pub threads: usize,
+    pub elapsed: Duration,
+}
+
+/// Reason why a file of `Batch::parse_files` has no tree.
+#[derive(Debug)]
+pub enum BatchFileError {
+    Io(std::io::Error),
+    Parse(ParseError),
+}
+
+/// Result of a file of `Batch::parse_files`: the input left after the tree, and the tree.
+pub type BatchFileResult = Result<(String, AbstractSyntaxTree), BatchFileError>;
+
+impl Display for BatchFileError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BatchFileError::Io(error) => write!(f, "{error}"),
+            BatchFileError::Parse(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl<I: Input + ?Sized> Batch<I> {
+    /// A batch running `parser` on as many threads as the machine can run in parallel.
+    pub fn new(parser: Arc<dyn Parser<I> + Send + Sync>) -> Batch<I> {
+        Batch {
+            parser,
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+        }
+    }
+
+    /// Sets the number of threads of the pool. Fewer threads are started when there are fewer inputs.
+    pub fn threads(mut self, threads: usize) -> Batch<I> {
+        if threads == 0 {
+            panic!("Tried to create a batch with no threads. This is illegal.");
+        }
+        self.threads = threads;
+        self
+    }
+
+    /// Parses each of `inputs`, returning their results in the same order.
+    pub fn parse<'a>(
+        &self,
+        inputs: &[&'a I],
+    ) -> (
+        Vec<ParseResult<'a, AbstractSyntaxTree<&'a I>, I>>,
+        BatchStatistics,
+    )
+    where
+        I: Sync,
+    {
+        let start = Instant::now();
+        let (results, threads) = self.run(inputs, |input| self.parser.parse(input));
+        let mut statistics = BatchStatistics {
+            inputs: inputs.len(),
+            units: inputs.iter().map(|input| input.input_len()).sum(),
+            threads,
+            ..BatchStatistics::default()
+        };
+        for result in &results {
+            match result {
+                Ok((remaining, _)) if remaining.input_len() == 0 => statistics.accepted += 1,
+                Ok(_) => statistics.partial += 1,
+                Err(_) => statistics.rejected += 1,
+            }
+        }
+        statistics.elapsed = start.elapsed();
+        (results, statistics)
+    }
+
+    /// Runs `work` on every item on the pool, and returns the results in the order of the items
+    /// along with the number of threads that were started.
+    fn run<T: Sync, R: Send>(&self, items: &[T], work: impl Fn(&T) -> R + Sync) -> (Vec<R>, usize) {
+        let threads = self.threads.min(items.len()).max(1);
+        let next = AtomicUsize::new(0);
+        let mut results: Vec<Option<R>> = items.iter().map(|_| None).collect();
+        thread::scope(|scope| {
+            let workers: Vec<_> = (0..threads)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut done = vec![];
+                        loop {
+                            let i = next.fetch_add(1, Ordering::Relaxed);
+                            if i >= items.len() {
+                                return done;
+                            }
+                            done.push((i, work(&items[i])));
+                        }
+                    })
+                })
+                .collect();
+            for worker in workers {
+                // A parser that panics makes the whole batch panic, as it would sequentially.
+                let done = worker
+                    .join()
+                    .unwrap_or_else(|e| std::panic::resume_unwind(e));
+                for (i, result) in done {
+                    results[i] = Some(result);
+                }
+            }
+        });
+        let results = results
+            .into_iter()
+            .map(|result| result.expect("Internal error. Please report."))
+            .collect();
+        (results, threads)
+    }
+}
+
+impl Batch<str> {
+    /// Reads and parses each of `paths`, returning the remaining input and the tree of each file in
+    /// the same order. Files are read by the threads of the pool.
+    pub fn parse_files<P: AsRef<Path> + Sync>(
+        &self,
+        paths: &[P],
+    ) -> (Vec<BatchFileResult>, BatchStatistics) {
+        let start = Instant::now();
+        let units = AtomicUsize::new(0);
+        let (results, threads) = self.run(paths, |path| {
+            let text = std::fs::read_to_string(path).map_err(BatchFileError::Io)?;
+            units.fetch_add(text.len(), Ordering::Relaxed);
+            match self.parser.parse_owned(&text) {
+                Ok((remaining, tree)) => Ok((remaining.to_string(), tree)),
+                Err(error) => Err(BatchFileError::Parse(error)),
+            }
+        });
+        let mut statistics = BatchStatistics {
+            inputs: paths.len(),
+            units: units.into_inner(),
+            threads,
+            ..BatchStatistics::default()
+        };
+        for result in &results {
+            match result {
+                Ok((remaining, _)) if remaining.is_empty() => statistics.accepted += 1,
+                Ok(_) => statistics.partial += 1,
+                Err(BatchFileError::Parse(_)) => statistics.rejected += 1,
+                Err(BatchFileError::Io(_)) => statistics.unreadable += 1,
+            }
+        }
+        statistics.elapsed = start.elapsed();
+        (results, statistics)
+    }
+}
@@ -1,17 +1,40 @@
 use crate::PropagatedParseError::{BecauseSubparserAccepted, BecauseSubparserRejected};
-use crate::brick::ParserIndex::{AcceptingState, Index, RejectingState};
+use crate::brick::ParserIndex::{
+    AcceptingState, Checkpoint, Commit, Index, RejectingState, Rollback,
+};
+use crate::ignore::Ignore as IgnoreParser;
 use crate::parser::AbstractSyntaxTree::{Branch, Ignore};
-use crate::parser::{AbstractSyntaxTree, Parser};
-use crate::{ParseError, ParseResult, ignore};
-use std::rc::Rc;
+use crate::parser::{AbstractSyntaxTree, Parser, Span};
+use crate::{Input, Local, ParseError, ParseResult, Share, Sharing, Symbol};
+use std::fmt::{Display, Formatter};
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ParserIndex {
     Index(usize),
+    /// Like `Index`, but also commits the brick: if it rejects from then on, its error is committed
+    /// (see `ParseError::committed`) and the bricks that run it reject too instead of trying alternatives.
+    Commit(usize),
+    /// Like `Index`, but also marks a checkpoint: the input position and the results of the brick
+    /// as they are when the transition is taken, to be restored by a `Rollback` transition.
+    /// The brick starts with a checkpoint at the beginning of its input.
+    Checkpoint(usize),
+    /// Like `Index`, but first restores the last checkpoint, so that the input consumed and the
+    /// results produced on an abandoned path do not end up in the output of the brick.
+    Rollback(usize),
     AcceptingState,
     RejectingState,
 }
 
+impl ParserIndex {
+    /// The index of the parser that the transition leads to, if it does not lead to a final state.
+    pub fn parser(self) -> Option<usize> {
+        match self {
+            Index(j) | Commit(j) | Checkpoint(j) | Rollback(j) => Some(j),
+            AcceptingState | RejectingState => None,
+        }
+    }
+}
+
 impl From<usize> for ParserIndex {
     fn from(index: usize) -> Self {
         Index(index)
@@ -23,40 +46,48 @@ impl From<usize> for ParserIndex {
 /// the behaviour of the brick when each parser accepts or refuses its input.
 /// When calling Brick::parse on some input, `parsers[0].parse` will be called with the provided input,
 /// and subsequent parsers will be called according to the dynamics specified in `on_success` and `on_failure`.
-pub struct Brick {
-    name: String,
-    parsers: Vec<Rc<dyn Parser>>,
+pub struct Brick<I: Input + ?Sized = str, K: Sharing = Local> {
+    name: Symbol,
+    parsers: Vec<K::Parser<I>>,
 
     /// `on_success` must be the same size as `parsers`. `on_success[i]` can be:
     /// - an index `ParserIndex::Index(j)`, which means that when `parsers[i]` accepts its input,
-    /// the remaining input will be sent to `parsers[j]`;
+    ///   the remaining input will be sent to `parsers[j]`;
     /// - the token `ParserIndex::AcceptingState`, which means that when `parsers[i]` accepts its input,
-    /// the brick will accept the input.
+    ///   the brick will accept the input.
     /// - the token `ParserIndex::RejectingState`, which means that when `parsers[i]` rejects its input,
-    /// the brick will reject the input.
+    ///   the brick will reject the input.
     pub on_success: Vec<ParserIndex>,
 
     /// `on_failure` works like `on_success`, determining what parser will be run when a parser rejects its input.
     pub on_failure: Vec<ParserIndex>,
 }
 
-impl Brick {
+impl<I: Input + ?Sized, K: Sharing> Brick<I, K> {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn parsers(&self) -> &[K::Parser<I>] {
+        &self.parsers
+    }
+
     /// `Brick::new` defines a new brick from a list of parsers, with default values for on_success and on_failure.
     /// The default behaviour makes the brick equivalent to the first of the provided parsers, whose result is wrapped in a AbstractSyntaxTree::branch.
-    pub fn new<T: ToString>(name: T, parsers: Vec<Rc<dyn Parser>>) -> Brick {
+    pub fn new<T: Into<Symbol>>(name: T, parsers: Vec<K::Parser<I>>) -> Brick<I, K> {
         let n = parsers.len();
         if n == 0 {
             panic!("Tried to create a brick with no parsers. This is illegal.");
         }
         Brick {
-            name: name.to_string(),
+            name: name.into(),
             parsers,
             on_success: vec![AcceptingState; n],
             on_failure: vec![RejectingState; n],
         }
     }
 
-    pub fn make_linear<T: ToString>(name: T, parsers: Vec<Rc<dyn Parser>>) -> Brick {
+    pub fn make_linear<T: Into<Symbol>>(name: T, parsers: Vec<K::Parser<I>>) -> Brick<I, K> {
         let n = parsers.len();
         if n == 0 {
             panic!("Tried to create a brick with no parsers. This is illegal.");
@@ -64,7 +95,7 @@ impl Brick {
         let mut on_success: Vec<ParserIndex> = (1..=n).map(ParserIndex::from).collect();
         on_success[n - 1] = AcceptingState;
         Brick {
-            name: name.to_string(),
+            name: name.into(),
             parsers,
             on_success,
             on_failure: vec![RejectingState; n],
@@ -73,7 +104,7 @@ impl Brick {
 
     /// Runs all parsers in the order in which they are given until one accepts.
     /// Accepts the input if a parser accepts, rejects the input if none accepts.
-    pub fn make_disjunction<T: ToString>(name: T, parsers: Vec<Rc<dyn Parser>>) -> Brick {
+    pub fn make_disjunction<T: Into<Symbol>>(name: T, parsers: Vec<K::Parser<I>>) -> Brick<I, K> {
         let n = parsers.len();
         if n == 0 {
             panic!("Tried to create a brick with no parsers. This is illegal.");
@@ -81,7 +112,7 @@ impl Brick {
         let mut on_failure: Vec<ParserIndex> = (1..=n).map(ParserIndex::from).collect();
         on_failure[n - 1] = RejectingState;
         Brick {
-            name: name.to_string(),
+            name: name.into(),
             parsers,
             on_success: vec![AcceptingState; n],
             on_failure,
@@ -91,9 +122,9 @@ impl Brick {
     /// Applies the parser and accepts the input. The input is consumed if and only if the parser accepted.
     /// It propagates the output of the wrapped parser, unlike Ignore.
     /// Can be wrapped inside an Ignore to optionally consume an element that has no effect on the meaning of the input.
-    pub fn maybe<T: ToString>(name: T, parser: Rc<dyn Parser>) -> Brick {
+    pub fn maybe<T: Into<Symbol>>(name: T, parser: K::Parser<I>) -> Brick<I, K> {
         Brick {
-            name: name.to_string(),
+            name: name.into(),
             parsers: vec![parser],
             on_success: vec![AcceptingState],
             on_failure: vec![AcceptingState],
@@ -103,79 +134,317 @@ impl Brick {
     /// Makes a brick that parses a chain that contains elements matched by parser, seperated by elements matched by separator.
     /// parser must match at least one element. It may or may not be allowed to match strings that end with the separator, by setting the allow_trailing_separator attribute accordingly. Even if we allow trailing separator, there must be at least one element parsed (internally, this works by parsing a separated string without trailing separator and then optionally parsing a separator).
     /// For example, if parser matches strings of numbers and separator matches operators, this will match operations.
-    pub fn make_separated<T: ToString>(
+    pub fn make_separated<T: Into<Symbol>>(
         name: T,
-        parser: Rc<dyn Parser>,
-        separator: Rc<dyn Parser>,
+        parser: K::Parser<I>,
+        separator: K::Parser<I>,
         allow_trailing_separator: bool,
-    ) -> Brick {
+    ) -> Brick<I, K>
+    where
+        Brick<I, K>: Share<I, K>,
+        IgnoreParser<I, K>: Share<I, K>,
+    {
+        let name: Symbol = name.into();
         if !allow_trailing_separator {
             Brick {
-                name: name.to_string(),
+                name,
                 parsers: vec![parser, separator],
                 on_success: vec![Index(1), Index(0)],
                 on_failure: vec![RejectingState, AcceptingState],
             }
         } else {
-            let trailing_separator = Rc::new(ignore::Ignore::new(Rc::new(Brick::maybe(
-                format!("{}_trailing_separator", name.to_string()),
-                separator.clone(),
-            ))));
-            Brick::make_linear(name, vec![parser, separator, trailing_separator])
+            let trailing_separator = IgnoreParser::<I, K>::new(
+                Self::maybe(format!("{name}_trailing_separator"), separator.clone()).share(),
+            )
+            .share();
+            Self::make_linear(name, vec![parser, separator, trailing_separator])
+        }
+    }
+
+    /// Defines a brick from a list of parsers and explicit transitions, and validates it, see `Brick::validate`.
+    pub fn try_new<T: Into<Symbol>>(
+        name: T,
+        parsers: Vec<K::Parser<I>>,
+        on_success: Vec<ParserIndex>,
+        on_failure: Vec<ParserIndex>,
+    ) -> Result<Brick<I, K>, Vec<BrickError>> {
+        let brick = Brick {
+            name: name.into(),
+            parsers,
+            on_success,
+            on_failure,
+        };
+        brick.validate()?;
+        Ok(brick)
+    }
+
+    /// Checks that the transitions of the brick are well-formed, so that mistakes show up before
+    /// parsing rather than as an index panic in the middle of it. Reports all the problems found:
+    /// - `on_success` or `on_failure` does not have one entry per parser;
+    /// - a transition targets an index that is out of range;
+    /// - a parser can never be run, because no transition leads to it from the first parser;
+    /// - a parser can be run but no sequence of transitions leads from it to `AcceptingState`.
+    ///
+    /// Nested parsers are not validated.
+    pub fn validate(&self) -> Result<(), Vec<BrickError>> {
+        let n = self.parsers.len();
+        let mut errors = vec![];
+        if n == 0 {
+            errors.push(BrickError::Empty {
+                brick: self.name.to_string(),
+            });
+            return Err(errors);
+        }
+        if self.on_success.len() != n || self.on_failure.len() != n {
+            errors.push(BrickError::LengthMismatch {
+                brick: self.name.to_string(),
+                parsers: n,
+                on_success: self.on_success.len(),
+                on_failure: self.on_failure.len(),
+            });
+            return Err(errors);
+        }
+
+        // Edges of the state machine, from each parser to the parsers it can lead to.
+        let mut successors: Vec<Vec<usize>> = vec![vec![]; n];
+        let mut accepting = vec![false; n];
+        for (i, successor) in successors.iter_mut().enumerate() {
+            for (on_success, transitions) in [(true, &self.on_success), (false, &self.on_failure)] {
+                match transitions[i] {
+                    AcceptingState => accepting[i] = true,
+                    RejectingState => {}
+                    target => match target.parser() {
+                        Some(j) if j >= n => errors.push(BrickError::TargetOutOfRange {
+                            brick: self.name.to_string(),
+                            index: i,
+                            on_success,
+                            target: j,
+                        }),
+                        Some(j) => successor.push(j),
+                        None => {}
+                    },
+                }
+            }
+        }
+
+        let mut reachable = vec![false; n];
+        let mut stack = vec![0];
+        while let Some(i) = stack.pop() {
+            if !std::mem::replace(&mut reachable[i], true) {
+                stack.extend(&successors[i]);
+            }
+        }
+
+        // `accepting` is extended to every parser that leads to an accepting parser, until a fixpoint is reached.
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for i in 0..n {
+                if !accepting[i] && successors[i].iter().any(|&j| accepting[j]) {
+                    accepting[i] = true;
+                    changed = true;
+                }
+            }
+        }
+
+        for i in 0..n {
+            if !reachable[i] {
+                errors.push(BrickError::Unreachable {
+                    brick: self.name.to_string(),
+                    index: i,
+                });
+            } else if !accepting[i] {
+                errors.push(BrickError::CannotAccept {
+                    brick: self.name.to_string(),
+                    index: i,
+                });
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// A problem found by `Brick::validate`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BrickError {
+    /// `on_success` or `on_failure` does not have one entry per parser.
+    LengthMismatch {
+        brick: String,
+        parsers: usize,
+        on_success: usize,
+        on_failure: usize,
+    },
+
+    /// `on_success[index]` (or `on_failure[index]` if `on_success` is false) is `ParserIndex::Index(target)`
+    /// but there is no parser at `target`.
+    TargetOutOfRange {
+        brick: String,
+        index: usize,
+        on_success: bool,
+        target: usize,
+    },
+
+    /// The parser at `index` is never run.
+    Unreachable { brick: String, index: usize },
+
+    /// The brick always rejects its input once it runs the parser at `index`.
+    CannotAccept { brick: String, index: usize },
+
+    /// The brick has no parsers.
+    Empty { brick: String },
+
+    /// Two states of a `BrickBuilder` have the same label.
+    DuplicateLabel { brick: String, label: String },
+
+    /// A transition of a `BrickBuilder` refers to a label that no state has.
+    UnknownLabel { brick: String, label: String },
+}
+
+impl Display for BrickError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BrickError::LengthMismatch {
+                brick,
+                parsers,
+                on_success,
+                on_failure,
+            } => write!(
+                f,
+                "brick \"{brick}\" has {parsers} parsers but {on_success} entries in on_success and {on_failure} in on_failure"
+            ),
+            BrickError::TargetOutOfRange {
+                brick,
+                index,
+                on_success,
+                target,
+            } => {
+                let transitions = if *on_success {
+                    "on_success"
+                } else {
+                    "on_failure"
+                };
+                write!(
+                    f,
+                    "brick \"{brick}\": {transitions}[{index}] targets parser {target}, which does not exist"
+                )
+            }
+            BrickError::Unreachable { brick, index } => {
+                write!(f, "brick \"{brick}\": parser {index} can never be run")
+            }
+            BrickError::CannotAccept { brick, index } => write!(
+                f,
+                "brick \"{brick}\": parser {index} can never lead to the accepting state"
+            ),
+            BrickError::Empty { brick } => write!(f, "brick \"{brick}\" has no parsers"),
+            BrickError::DuplicateLabel { brick, label } => {
+                write!(
+                    f,
+                    "brick \"{brick}\": label \"{label}\" is used by several states"
+                )
+            }
+            BrickError::UnknownLabel { brick, label } => {
+                write!(f, "brick \"{brick}\": no state is labelled \"{label}\"")
+            }
         }
     }
 }
 
-impl Parser for Brick {
-    fn parse<'a>(&self, input: &'a str) -> ParseResult<'a, AbstractSyntaxTree> {
+impl<I: Input + ?Sized, K: Sharing> Parser<I> for Brick<I, K> {
+    fn parse_at<'a>(
+        &self,
+        input: &'a I,
+        offset: usize,
+    ) -> ParseResult<'a, AbstractSyntaxTree<&'a I>, I> {
         let name = self.name.clone();
         let mut remaining = input;
-        let mut results: Vec<AbstractSyntaxTree> = vec![];
+        let mut results: Vec<AbstractSyntaxTree<&'a I>> = vec![];
         let mut i = Index(0);
         let mut last_parser_index: Option<usize> = None;
-        let mut last_parser_accepted: bool = false;
-        let mut last_failure: Option<ParseError> = None;
-        while let Index(j) = i {
+        // The result of the last sub-parser is kept apart from the others until the next one runs,
+        // so that a rejection because it accepted can report its tree, even an ignored one.
+        let mut last: Option<Result<AbstractSyntaxTree<&'a I>, ParseError<&'a I>>> = None;
+        let mut committed = false;
+        let mut checkpoint = (input, 0);
+        while let Some(j) = i.parser() {
+            if let Some(Ok(res)) = last.take()
+                && !matches!(res, Ignore(_))
+            {
+                results.push(res);
+            }
+            match i {
+                Commit(_) => committed = true,
+                Checkpoint(_) => checkpoint = (remaining, results.len()),
+                Rollback(_) => {
+                    remaining = checkpoint.0;
+                    results.truncate(checkpoint.1);
+                }
+                _ => {}
+            }
             last_parser_index = Some(j);
-            match self.parsers[j].parse(remaining) {
+            // Every input we hand to a sub-parser is a suffix of our own input, so its position
+            // in the original string follows from how much we have consumed so far.
+            let position = offset + input.input_len() - remaining.input_len();
+            match self.parsers[j].parse_at(remaining, position) {
                 Ok((rem, res)) => {
                     remaining = rem;
-                    if res != Ignore {
-                        results.push(res);
-                    }
                     i = self.on_success[j];
-                    last_parser_accepted = true;
+                    last = Some(Ok(res));
                 }
                 Err(err) => {
-                    i = self.on_failure[j];
-                    last_parser_accepted = false;
-                    last_failure = Some(err);
+                    // A committed error is not recovered from, whatever the transitions say.
+                    i = if err.committed {
+                        committed = true;
+                        RejectingState
+                    } else {
+                        self.on_failure[j]
+                    };
+                    last = Some(Err(err));
                 }
             }
         }
         match i {
-            AcceptingState => Ok((remaining, Branch(name, results))),
+            AcceptingState => {
+                if let Some(Ok(res)) = last
+                    && !matches!(res, Ignore(_))
+                {
+                    results.push(res);
+                }
+                Ok((
+                    remaining,
+                    Branch(
+                        name,
+                        results.into(),
+                        Span::consumed(input, remaining, offset),
+                    ),
+                ))
+            }
             RejectingState => {
-                let propagation = if last_parser_accepted {
-                    let last_parser_name = self.parsers
-                        [last_parser_index.expect("Internal error. Please report.")]
-                    .get_name_clone();
-                    BecauseSubparserAccepted(
-                        last_parser_name,
-                        results.pop().expect("Internal error. Please report."),
-                    )
-                } else {
-                    BecauseSubparserRejected(Box::new(
-                        last_failure.expect("Internal error. Please report."),
-                    ))
+                let propagation = match last.expect("Internal error. Please report.") {
+                    Ok(res) => {
+                        let last_parser_name = self.parsers
+                            [last_parser_index.expect("Internal error. Please report.")]
+                        .get_name_clone();
+                        BecauseSubparserAccepted(last_parser_name, Box::new(res))
+                    }
+                    Err(err) => BecauseSubparserRejected(Box::new(err)),
                 };
-                Err(ParseError::new(
+                let position = offset + input.input_len() - remaining.input_len();
+                let mut error = ParseError::new(
                     format!(
-                        "Brick failed to parse \"{input}\". Error occurred when trying to parse \"{remaining}\"."
+                        "Brick \"{}\" starting at offset {offset} failed at offset {position}.",
+                        self.name
                     ),
                     self.name.clone(),
+                    position,
                     propagation,
-                ))
+                );
+                error.committed = committed;
+                Err(error)
             }
             _ => {
                 panic!("Internal error. Please report.")
@@ -183,6 +452,36 @@ impl Parser for Brick {
         }
     }
     fn get_name_clone(&self) -> String {
-        self.name.clone()
+        self.name.to_string()
+    }
+
+    fn as_brick(&self) -> Option<&dyn AnyBrick<I>> {
+        Some(self)
+    }
+}
+
+/// A brick whatever its sharing, as returned by `Parser::as_brick`.
+pub trait AnyBrick<I: Input + ?Sized> {
+    fn name(&self) -> &str;
+    fn parsers(&self) -> Vec<&dyn Parser<I>>;
+    fn on_success(&self) -> &[ParserIndex];
+    fn on_failure(&self) -> &[ParserIndex];
+}
+
+impl<I: Input + ?Sized, K: Sharing> AnyBrick<I> for Brick<I, K> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn parsers(&self) -> Vec<&dyn Parser<I>> {
+        self.parsers.iter().map(K::as_dyn).collect()
+    }
+
+    fn on_success(&self) -> &[ParserIndex] {
+        &self.on_success
+    }
+
+    fn on_failure(&self) -> &[ParserIndex] {
+        &self.on_failure
     }
 }
@@ -0,0 +1,149 @@
+use crate::brick::Brick;
+use crate::brick::ParserIndex::{
+    AcceptingState, Checkpoint, Commit, Index, RejectingState, Rollback,
+};
+use crate::{BrickError, Input, Local, ParserIndex, Sharing, Symbol};
+use std::collections::HashMap;
+
+/// Where a brick goes after one of its states: another state, designated by its label, or one of
+/// the final states.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    State(String),
+    /// Goes to the state with this label and commits the brick, see `ParserIndex::Commit`.
+    Commit(String),
+    /// Goes to the state with this label and marks a checkpoint, see `ParserIndex::Checkpoint`.
+    Checkpoint(String),
+    /// Restores the last checkpoint and goes to the state with this label, see `ParserIndex::Rollback`.
+    Rollback(String),
+    Accept,
+    Reject,
+}
+
+impl From<&str> for Target {
+    fn from(label: &str) -> Self {
+        Target::State(label.to_string())
+    }
+}
+
+/// Builds a `Brick` whose states are designated by labels rather than by their index in the list
+/// of parsers, so that adding a state does not shift the transitions of the others.
+///
+/// ```
+/// # use parser::{BrickBuilder, StringParser, Target};
+/// # use std::rc::Rc;
+/// let brick = BrickBuilder::new("greeting")
+///     .state("hello", Rc::new(StringParser::new("hello")))
+///     .state("name", Rc::new(StringParser::new(" world")))
+///     .on_success("hello", "name")
+///     .on_success("name", Target::Accept)
+///     .build()
+///     .unwrap();
+/// ```
+///
+/// The first state is the one the brick starts from. By default, every state goes to
+/// `Target::Accept` when its parser accepts and to `Target::Reject` when it rejects, like in `Brick::new`.
+pub struct BrickBuilder<I: Input + ?Sized = str, K: Sharing = Local> {
+    name: Symbol,
+    labels: Vec<String>,
+    parsers: Vec<K::Parser<I>>,
+    on_success: Vec<Target>,
+    on_failure: Vec<Target>,
+
+    /// Transitions set from a label that was never given to a state, reported when building.
+    unknown_sources: Vec<String>,
+}
+
+impl<I: Input + ?Sized, K: Sharing> BrickBuilder<I, K> {
+    pub fn new<T: Into<Symbol>>(name: T) -> BrickBuilder<I, K> {
+        BrickBuilder {
+            name: name.into(),
+            labels: vec![],
+            parsers: vec![],
+            on_success: vec![],
+            on_failure: vec![],
+            unknown_sources: vec![],
+        }
+    }
+
+    /// Adds a state labelled `label` that runs `parser`.
+    pub fn state<T: ToString>(mut self, label: T, parser: K::Parser<I>) -> BrickBuilder<I, K> {
+        self.labels.push(label.to_string());
+        self.parsers.push(parser);
+        self.on_success.push(Target::Accept);
+        self.on_failure.push(Target::Reject);
+        self
+    }
+
+    /// Sets where the brick goes when the parser of state `from` accepts its input.
+    pub fn on_success<T: Into<Target>>(mut self, from: &str, to: T) -> BrickBuilder<I, K> {
+        match self.position(from) {
+            Some(i) => self.on_success[i] = to.into(),
+            None => self.unknown_sources.push(from.to_string()),
+        }
+        self
+    }
+
+    /// Sets where the brick goes when the parser of state `from` rejects its input.
+    pub fn on_failure<T: Into<Target>>(mut self, from: &str, to: T) -> BrickBuilder<I, K> {
+        match self.position(from) {
+            Some(i) => self.on_failure[i] = to.into(),
+            None => self.unknown_sources.push(from.to_string()),
+        }
+        self
+    }
+
+    fn position(&self, label: &str) -> Option<usize> {
+        self.labels.iter().position(|l| l == label)
+    }
+
+    /// Compiles the labels down to `ParserIndex`es and validates the resulting brick, see `Brick::validate`.
+    pub fn build(self) -> Result<Brick<I, K>, Vec<BrickError>> {
+        let mut errors = vec![];
+        let mut indices: HashMap<&str, usize> = HashMap::new();
+        for (i, label) in self.labels.iter().enumerate() {
+            if indices.insert(label, i).is_some() {
+                errors.push(BrickError::DuplicateLabel {
+                    brick: self.name.to_string(),
+                    label: label.clone(),
+                });
+            }
+        }
+        for label in &self.unknown_sources {
+            errors.push(BrickError::UnknownLabel {
+                brick: self.name.to_string(),
+                label: label.clone(),
+            });
+        }
+
+        let mut compile = |target: &Target| match target {
+            Target::Accept => AcceptingState,
+            Target::Reject => RejectingState,
+            Target::State(label)
+            | Target::Commit(label)
+            | Target::Checkpoint(label)
+            | Target::Rollback(label) => match indices.get(label.as_str()) {
+                Some(&i) => match target {
+                    Target::Commit(_) => Commit(i),
+                    Target::Checkpoint(_) => Checkpoint(i),
+                    Target::Rollback(_) => Rollback(i),
+                    _ => Index(i),
+                },
+                None => {
+                    errors.push(BrickError::UnknownLabel {
+                        brick: self.name.to_string(),
+                        label: label.clone(),
+                    });
+                    RejectingState
+                }
+            },
+        };
+        let on_success: Vec<ParserIndex> = self.on_success.iter().map(&mut compile).collect();
+        let on_failure: Vec<ParserIndex> = self.on_failure.iter().map(&mut compile).collect();
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        Brick::try_new(self.name, self.parsers, on_success, on_failure)
+    }
+}
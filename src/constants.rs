@@ -1,12 +1,11 @@
-use std::cell::LazyCell;
 use std::collections::HashSet;
-use std::rc::Rc;
+use std::sync::{Arc, LazyLock};
 
-pub const NUMERICS: LazyCell<Rc<HashSet<char>>> =
-    LazyCell::new(|| HashSet::from_iter('0'..='9').into());
+pub static NUMERICS: LazyLock<Arc<HashSet<char>>> =
+    LazyLock::new(|| HashSet::from_iter('0'..='9').into());
 
-pub const LOWERCASE_ENGLISH: LazyCell<Rc<HashSet<char>>> =
-    LazyCell::new(|| HashSet::from_iter('a'..='z').into());
+pub static LOWERCASE_ENGLISH: LazyLock<Arc<HashSet<char>>> =
+    LazyLock::new(|| HashSet::from_iter('a'..='z').into());
 
-pub const UPPERCASE_ENGLISH: LazyCell<Rc<HashSet<char>>> =
-    LazyCell::new(|| HashSet::from_iter('A'..='Z').into());
+pub static UPPERCASE_ENGLISH: LazyLock<Arc<HashSet<char>>> =
+    LazyLock::new(|| HashSet::from_iter('A'..='Z').into());
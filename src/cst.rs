@@ -0,0 +1,154 @@
+use crate::AbstractSyntaxTree::{self, Branch, Ignore, Leaf};
+use crate::{Input, Span, Symbol};
+
+/// Input that the abstract syntax tree leaves out, such as whitespaces and comments matched by
+/// `Ignore` parsers. Consecutive ignored matches form a single trivia.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trivia<L = String> {
+    pub text: L,
+    pub span: Span,
+}
+
+/// Lossless counterpart of `AbstractSyntaxTree`, for tools that rewrite the input rather than
+/// interpret it, like formatters. The ignored input is kept as trivia attached to the neighbouring
+/// leaves, so that `unparse` gives back the parsed input byte for byte:
+/// - `Leaf(tag, text, span, leading)` holds the trivia between the previous leaf and this one;
+/// - `Branch(tag, children, span, trailing)` holds the trivia after its last leaf, up to its end;
+/// - `Ignore(trivia, span)` is only found at the root, when the whole match was ignored.
+///
+/// Spans are those of the abstract syntax tree, and do not include the attached trivia.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ConcreteSyntaxTree<L = String> {
+    Leaf(Symbol, L, Span, Option<Trivia<L>>),
+    Branch(Symbol, Vec<ConcreteSyntaxTree<L>>, Span, Option<Trivia<L>>),
+    Ignore(Option<Trivia<L>>, Span),
+}
+
+/// Trivia covering `start..end` of `input`, if the range is not empty.
+fn trivia<I: Input + ?Sized>(input: &I, start: usize, end: usize) -> Option<Trivia<&I>> {
+    if start >= end {
+        return None;
+    }
+    let (_, rest) = input.split_input_at(start);
+    let (text, _) = rest.split_input_at(end - start);
+    Some(Trivia {
+        text,
+        span: Span::new(start, end),
+    })
+}
+
+impl<'a, I: Input + ?Sized> AbstractSyntaxTree<&'a I> {
+    /// Builds the concrete syntax tree of a tree produced by parsing `input`, which must be the
+    /// input given to the outermost parser. The ignored input is recovered from the gaps between
+    /// the spans of the leaves.
+    pub fn to_concrete(&self, input: &'a I) -> ConcreteSyntaxTree<&'a I> {
+        let mut position = self.span().start;
+        self.to_concrete_from(input, &mut position)
+    }
+
+    /// `position` is the end of the previous leaf, where the trivia of the next leaf starts.
+    fn to_concrete_from(&self, input: &'a I, position: &mut usize) -> ConcreteSyntaxTree<&'a I> {
+        match self {
+            Leaf(tag, text, span) => {
+                let leading = trivia(input, *position, span.start);
+                *position = span.end;
+                ConcreteSyntaxTree::Leaf(tag.clone(), *text, *span, leading)
+            }
+            Branch(tag, children, span) => {
+                let children = children
+                    .iter()
+                    .filter(|child| !matches!(child, Ignore(_)))
+                    .map(|child| child.to_concrete_from(input, position))
+                    .collect();
+                let trailing = trivia(input, *position, span.end);
+                *position = (*position).max(span.end);
+                ConcreteSyntaxTree::Branch(tag.clone(), children, *span, trailing)
+            }
+            Ignore(span) => {
+                let ignored = trivia(input, *position, span.end);
+                *position = (*position).max(span.end);
+                ConcreteSyntaxTree::Ignore(ignored, *span)
+            }
+        }
+    }
+}
+
+impl<L> ConcreteSyntaxTree<L> {
+    pub fn span(&self) -> Span {
+        match self {
+            ConcreteSyntaxTree::Leaf(_, _, span, _)
+            | ConcreteSyntaxTree::Branch(_, _, span, _)
+            | ConcreteSyntaxTree::Ignore(_, span) => *span,
+        }
+    }
+
+    /// Calls `f` on the texts of the tree in input order: trivia and leaves alike.
+    fn for_each_text<'t>(&'t self, f: &mut impl FnMut(&'t L)) {
+        match self {
+            ConcreteSyntaxTree::Leaf(_, text, _, leading) => {
+                if let Some(trivia) = leading {
+                    f(&trivia.text);
+                }
+                f(text);
+            }
+            ConcreteSyntaxTree::Branch(_, children, _, trailing) => {
+                for child in children {
+                    child.for_each_text(f);
+                }
+                if let Some(trivia) = trailing {
+                    f(&trivia.text);
+                }
+            }
+            ConcreteSyntaxTree::Ignore(ignored, _) => {
+                if let Some(trivia) = ignored {
+                    f(&trivia.text);
+                }
+            }
+        }
+    }
+}
+
+impl<L: AsRef<str>> ConcreteSyntaxTree<L> {
+    /// Writes the tree back as text: the parsed input, trivia included.
+    pub fn unparse(&self) -> String {
+        let mut output = String::new();
+        self.for_each_text(&mut |text| output.push_str(text.as_ref()));
+        output
+    }
+}
+
+impl<I: ToOwned + ?Sized> Trivia<&I> {
+    pub fn into_owned(self) -> Trivia<I::Owned> {
+        Trivia {
+            text: self.text.to_owned(),
+            span: self.span,
+        }
+    }
+}
+
+impl<I: ToOwned + ?Sized> ConcreteSyntaxTree<&I> {
+    pub fn into_owned(self) -> ConcreteSyntaxTree<I::Owned> {
+        match self {
+            ConcreteSyntaxTree::Leaf(tag, text, span, leading) => ConcreteSyntaxTree::Leaf(
+                tag,
+                text.to_owned(),
+                span,
+                leading.map(Trivia::into_owned),
+            ),
+            ConcreteSyntaxTree::Branch(tag, children, span, trailing) => {
+                ConcreteSyntaxTree::Branch(
+                    tag,
+                    children
+                        .into_iter()
+                        .map(ConcreteSyntaxTree::into_owned)
+                        .collect(),
+                    span,
+                    trailing.map(Trivia::into_owned),
+                )
+            }
+            ConcreteSyntaxTree::Ignore(ignored, span) => {
+                ConcreteSyntaxTree::Ignore(ignored.map(Trivia::into_owned), span)
+            }
+        }
+    }
+}
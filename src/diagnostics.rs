@@ -0,0 +1,103 @@
+use crate::PropagatedParseError::{Atomic, BecauseSubparserAccepted};
+use crate::utils::ParseError;
+use std::fmt::Write;
+
+/// Maps byte offsets of a source string to line and column numbers.
+/// Lines and columns are 1-based; columns count characters, not bytes.
+pub struct LineIndex<'a> {
+    source: &'a str,
+    /// Byte offset of the first character of each line.
+    line_starts: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    pub fn new(source: &'a str) -> LineIndex<'a> {
+        let line_starts = std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        LineIndex {
+            source,
+            line_starts,
+        }
+    }
+
+    /// Returns the `(line, column)` of the character at byte `offset`.
+    /// Offsets past the end of the source are clamped to the end.
+    pub fn line_column(&self, offset: usize) -> (usize, usize) {
+        let offset = self.clamp(offset);
+        let line = self.line_starts.partition_point(|&start| start <= offset) - 1;
+        let column = self.source[self.line_starts[line]..offset].chars().count() + 1;
+        (line + 1, column)
+    }
+
+    /// Returns the text of the 1-based line `line`, without its line terminator.
+    pub fn line(&self, line: usize) -> &'a str {
+        let start = self.line_starts[line - 1];
+        let end = self
+            .line_starts
+            .get(line)
+            .map_or(self.source.len(), |&next| next - 1);
+        self.source[start..end].trim_end_matches('\r')
+    }
+
+    fn clamp(&self, offset: usize) -> usize {
+        let mut offset = offset.min(self.source.len());
+        while !self.source.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        offset
+    }
+}
+
+impl<L> ParseError<L> {
+    /// Renders the error against the source that was parsed, pointing a caret at the position
+    /// where the innermost failure occurred, e.g.
+    ///
+    /// ```text
+    /// error: expected "b" but got "x"
+    ///  --> 2:5 (in "keyword")
+    ///   |
+    /// 2 | foo xar
+    ///   |     ^ expected "b", found "x"
+    /// ```
+    pub fn render(&self, source: &str) -> String {
+        let innermost = self.innermost();
+        let index = LineIndex::new(source);
+        let (line, column) = index.line_column(innermost.offset);
+        let text = index.line(line);
+
+        let label = match &innermost.propagation {
+            Atomic(actual, expected) if actual.is_empty() => {
+                format!("expected \"{expected}\", found end of input")
+            }
+            Atomic(actual, expected) if expected.is_empty() => {
+                format!("expected end of input, found \"{actual}\"")
+            }
+            Atomic(actual, expected) => {
+                format!("expected \"{expected}\", found \"{actual}\"")
+            }
+            BecauseSubparserAccepted(name, _) => format!("\"{name}\" is not allowed here"),
+            _ => unreachable!("innermost errors never propagate a rejection"),
+        };
+
+        // Tabs are kept so that the caret lines up with the source line in a terminal.
+        let padding: String = text
+            .chars()
+            .take(column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let gutter = " ".repeat(line.to_string().len());
+
+        let mut rendered = String::new();
+        let _ = writeln!(rendered, "error: {}", innermost.message);
+        let _ = writeln!(
+            rendered,
+            "{gutter}--> {line}:{column} (in \"{}\")",
+            innermost.parser_name
+        );
+        let _ = writeln!(rendered, "{gutter} |");
+        let _ = writeln!(rendered, "{line} | {text}");
+        let _ = write!(rendered, "{gutter} | {padding}^ {label}");
+        rendered
+    }
+}
@@ -0,0 +1,115 @@
+use crate::brick::ParserIndex::{AcceptingState, Checkpoint, Commit, Rollback};
+use crate::brick::{AnyBrick, Brick};
+use crate::{Input, Parser, Sharing};
+use std::collections::HashMap;
+use std::fmt::Write;
+
+/// Escapes `text` so that it can be used in a double-quoted DOT string.
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Writes the bricks reachable from a root brick as DOT clusters, each brick being written once
+/// even if it is shared or recursive.
+struct DotWriter {
+    output: String,
+    /// Number of each brick that was already written, by address.
+    written: HashMap<*const (), usize>,
+}
+
+impl DotWriter {
+    /// Writes `brick` if it was not written yet and returns its number.
+    fn brick<I: Input + ?Sized>(&mut self, brick: &dyn AnyBrick<I>) -> usize {
+        let address = brick as *const dyn AnyBrick<I> as *const ();
+        if let Some(&number) = self.written.get(&address) {
+            return number;
+        }
+        let b = self.written.len();
+        self.written.insert(address, b);
+
+        let _ = writeln!(self.output, "  subgraph cluster_{b} {{");
+        let _ = writeln!(self.output, "    label=\"{}\";", escape(brick.name()));
+        let _ = writeln!(self.output, "    b{b}_start [shape=point];");
+        let _ = writeln!(
+            self.output,
+            "    b{b}_accept [label=\"accept\", shape=doublecircle];"
+        );
+        let _ = writeln!(
+            self.output,
+            "    b{b}_reject [label=\"reject\", shape=circle];"
+        );
+        for (i, parser) in brick.parsers().iter().enumerate() {
+            let _ = writeln!(
+                self.output,
+                "    b{b}_s{i} [label=\"{i}: {}\"];",
+                escape(&parser.get_name_clone())
+            );
+        }
+        let _ = writeln!(self.output, "    b{b}_start -> b{b}_s0;");
+        for i in 0..brick.parsers().len() {
+            for (transitions, style) in [
+                (brick.on_success(), "color=darkgreen"),
+                (brick.on_failure(), "color=red, style=dashed"),
+            ] {
+                let transition = transitions.get(i).copied();
+                let target = match transition {
+                    Some(AcceptingState) => format!("b{b}_accept"),
+                    _ => match transition.and_then(|t| t.parser()) {
+                        Some(j) => format!("b{b}_s{j}"),
+                        None => format!("b{b}_reject"),
+                    },
+                };
+                let label = match transition {
+                    Some(Commit(_)) => ", label=\"commit\"",
+                    Some(Checkpoint(_)) => ", label=\"checkpoint\"",
+                    Some(Rollback(_)) => ", label=\"rollback\"",
+                    _ => "",
+                };
+                let _ = writeln!(self.output, "    b{b}_s{i} -> {target} [{style}{label}];");
+            }
+        }
+        let _ = writeln!(self.output, "  }}");
+
+        // Nested bricks are written after the cluster of their parent, since clusters cannot overlap.
+        for (i, parser) in brick.parsers().iter().enumerate() {
+            if let Some(nested) = self.nested(*parser) {
+                let _ = writeln!(
+                    self.output,
+                    "  b{b}_s{i} -> b{nested}_start [style=dotted, arrowhead=empty];"
+                );
+            }
+        }
+        b
+    }
+
+    /// Writes the brick run by `parser`, looking through wrappers, and returns its number.
+    fn nested<I: Input + ?Sized>(&mut self, parser: &dyn Parser<I>) -> Option<usize> {
+        if let Some(brick) = parser.as_brick() {
+            return Some(self.brick(brick));
+        }
+        let mut nested = None;
+        parser.with_wrapped(&mut |wrapped| nested = self.nested(wrapped));
+        nested
+    }
+}
+
+impl<I: Input + ?Sized, K: Sharing> Brick<I, K> {
+    /// Exports the brick as a Graphviz DOT graph, along with the bricks it runs, recursively.
+    /// Each brick is drawn as a cluster of states, one per parser, with solid green edges for
+    /// `on_success` transitions and dashed red edges for `on_failure` transitions; commit,
+    /// checkpoint and rollback transitions are labelled as such.
+    /// A dotted edge goes from a state to the entry point of the brick that it runs.
+    pub fn to_dot(&self) -> String {
+        let mut writer = DotWriter {
+            output: String::new(),
+            written: HashMap::new(),
+        };
+        let _ = writeln!(writer.output, "digraph \"{}\" {{", escape(self.name()));
+        let _ = writeln!(writer.output, "  node [shape=box];");
+        writer.brick(self);
+        writer.output.push_str("}\n");
+        writer.output
+    }
+}
@@ -0,0 +1,201 @@
+use crate::AbstractSyntaxTree::{Branch, Ignore, Leaf};
+use crate::{AbstractSyntaxTree, Span, Symbol};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// Conversion of a tree into a user type. It can be derived with `#[derive(FromAst)]` from the
+/// companion crate `parser_derive`, re-exported by this crate with the `derive` feature:
+/// - a struct is converted from a node tagged with the name of the struct in snake case, each field
+///   being converted from the child at the same position. A leaf is accepted for a struct with a
+///   single field, which is then converted from the leaf itself;
+/// - an enum is converted according to the tag of the node: each variant is converted like a
+///   struct from the node tagged with the name of the variant in snake case.
+///
+/// The expected tag can be set with `#[from_ast(tag = "...")]` on the struct or the variant.
+/// `L` is the type of the leaves, see `AbstractSyntaxTree`; derived implementations are for leaves
+/// that are text (`&str` or `String`), which numbers and strings are parsed from with `FromStr`.
+///
+/// ```
+/// # #[cfg(feature = "derive")]
+/// # {
+/// # use parser::{Brick, FromAst, NUMERICS, AllWordsFromAlphabet, Parser, StringParser};
+/// # use std::rc::Rc;
+/// #[derive(FromAst, Debug, PartialEq)]
+/// struct Sum {
+///     left: Number,
+///     right: Number,
+/// }
+///
+/// #[derive(FromAst, Debug, PartialEq)]
+/// struct Number(i64);
+///
+/// let number = Rc::new(AllWordsFromAlphabet {
+///     name: "number".into(),
+///     alphabet: NUMERICS.clone(),
+///     allow_empty_word: false,
+/// });
+/// let plus = Rc::new(parser::Ignore::new(Rc::new(StringParser::new("+"))));
+/// let sum = Brick::make_linear("sum", vec![number.clone(), plus, number]);
+/// let (_, tree) = sum.parse("1+2").unwrap();
+/// assert_eq!(Sum::from_ast(&tree), Ok(Sum { left: Number(1), right: Number(2) }));
+///
+/// #[derive(FromAst, Debug, PartialEq)]
+/// enum Expression {
+///     Sum(Box<Expression>, Box<Expression>),
+///     #[from_ast(tag = "number")]
+///     Literal(i64),
+/// }
+/// assert_eq!(
+///     Expression::from_ast(&tree),
+///     Ok(Expression::Sum(
+///         Box::new(Expression::Literal(1)),
+///         Box::new(Expression::Literal(2))
+///     ))
+/// );
+/// let error = Number::from_ast(&tree).unwrap_err();
+/// assert_eq!(error.message, "expected \"number\", found branch \"sum\"");
+/// # }
+/// ```
+pub trait FromAst<L = String>: Sized {
+    fn from_ast(tree: &AbstractSyntaxTree<L>) -> Result<Self, FromAstError>;
+}
+
+/// Reason why a tree could not be converted, with the span of the node at fault.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FromAstError {
+    pub message: String,
+    pub span: Span,
+}
+
+impl Display for FromAstError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} at {}..{}",
+            self.message, self.span.start, self.span.end
+        )
+    }
+}
+
+fn describe<L>(tree: &AbstractSyntaxTree<L>) -> String {
+    match tree {
+        Leaf(tag, _, _) => format!("leaf \"{tag}\""),
+        Branch(tag, _, _) => format!("branch \"{tag}\""),
+        Ignore(_) => "ignored input".to_string(),
+    }
+}
+
+impl FromAstError {
+    /// Error for a node whose tag is none of `expected`.
+    pub fn unexpected_tag<L>(tree: &AbstractSyntaxTree<L>, expected: &[&str]) -> FromAstError {
+        let expected: Vec<String> = expected.iter().map(|tag| format!("\"{tag}\"")).collect();
+        FromAstError {
+            message: format!(
+                "expected one of {}, found {}",
+                expected.join(", "),
+                describe(tree)
+            ),
+            span: tree.span(),
+        }
+    }
+}
+
+/// Returns the nodes that the `count` fields of a type tagged `tag` are converted from, checking
+/// the shape of `tree`. Used by the code generated by `#[derive(FromAst)]`.
+#[doc(hidden)]
+pub fn fields_of<'t, L>(
+    tree: &'t AbstractSyntaxTree<L>,
+    tag: &Symbol,
+    count: usize,
+) -> Result<Vec<&'t AbstractSyntaxTree<L>>, FromAstError> {
+    let error = |message: String| {
+        Err(FromAstError {
+            message,
+            span: tree.span(),
+        })
+    };
+    match tree {
+        Leaf(t, _, _) | Branch(t, _, _) if t != tag => {
+            error(format!("expected \"{tag}\", found {}", describe(tree)))
+        }
+        Leaf(..) if count == 1 => Ok(vec![tree]),
+        Leaf(..) if count == 0 => Ok(vec![]),
+        Leaf(..) => error(format!(
+            "expected a branch \"{tag}\" with {count} children, found a leaf"
+        )),
+        Branch(_, children, _) => {
+            let children: Vec<_> = children
+                .iter()
+                .filter(|child| !matches!(child, Ignore(_)))
+                .collect();
+            if children.len() == count {
+                Ok(children)
+            } else {
+                error(format!(
+                    "expected {count} children in \"{tag}\", found {}",
+                    children.len()
+                ))
+            }
+        }
+        Ignore(_) => error(format!("expected \"{tag}\", found ignored input")),
+    }
+}
+
+/// Converts the text of a leaf with `FromStr`.
+fn from_leaf<T: FromStr, L: AsRef<str>>(
+    tree: &AbstractSyntaxTree<L>,
+    type_name: &str,
+) -> Result<T, FromAstError>
+where
+    T::Err: Display,
+{
+    match tree {
+        Leaf(_, text, span) => text.as_ref().parse().map_err(|e| FromAstError {
+            message: format!("cannot convert \"{}\" to {type_name}: {e}", text.as_ref()),
+            span: *span,
+        }),
+        _ => Err(FromAstError {
+            message: format!("expected a leaf, found {}", describe(tree)),
+            span: tree.span(),
+        }),
+    }
+}
+
+macro_rules! from_leaf {
+    ($($t:ty),*) => {
+        $(
+            impl<L: AsRef<str>> FromAst<L> for $t {
+                fn from_ast(tree: &AbstractSyntaxTree<L>) -> Result<Self, FromAstError> {
+                    from_leaf(tree, stringify!($t))
+                }
+            }
+        )*
+    };
+}
+
+from_leaf!(
+    String, char, bool, i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize, f32, f64
+);
+
+impl<L, T: FromAst<L>> FromAst<L> for Box<T> {
+    fn from_ast(tree: &AbstractSyntaxTree<L>) -> Result<Self, FromAstError> {
+        T::from_ast(tree).map(Box::new)
+    }
+}
+
+/// A list is converted from the children of a branch, whatever its tag.
+impl<L, T: FromAst<L>> FromAst<L> for Vec<T> {
+    fn from_ast(tree: &AbstractSyntaxTree<L>) -> Result<Self, FromAstError> {
+        match tree {
+            Branch(_, children, _) => children
+                .iter()
+                .filter(|child| !matches!(child, Ignore(_)))
+                .map(T::from_ast)
+                .collect(),
+            _ => Err(FromAstError {
+                message: format!("expected a branch, found {}", describe(tree)),
+                span: tree.span(),
+            }),
+        }
+    }
+}
@@ -1,32 +1,42 @@
-use crate::{AbstractSyntaxTree, ParseResult, Parser};
-use std::rc::Rc;
+use crate::{AbstractSyntaxTree, Input, Local, ParseResult, Parser, Sharing, Span, Symbol};
 
 /// Ignore is a parser that applies the wrapped parser.
 /// If the wrapped parser fails, Ignore fails and propagate the error of the wrapped parser.
-/// If the wrapped parser accepts, Ignore accepts and outputs an Ignore leaf.
+/// If the wrapped parser accepts, Ignore accepts, consumes what the wrapped parser matched and outputs an Ignore leaf.
 /// This is useful for elements that need to be parsed but are not used when processing the abstract syntax tree, like whitespaces and trailing commas.
-pub struct Ignore {
-    name: String,
-    parser: Rc<dyn Parser>,
+pub struct Ignore<I: Input + ?Sized = str, K: Sharing = Local> {
+    name: Symbol,
+    parser: K::Parser<I>,
 }
 
-impl Parser for Ignore {
-    fn parse<'a>(&self, input: &'a str) -> ParseResult<'a, AbstractSyntaxTree> {
-        match self.parser.parse(input) {
-            Ok(_) => Ok((input, AbstractSyntaxTree::Ignore)),
+impl<I: Input + ?Sized, K: Sharing> Parser<I> for Ignore<I, K> {
+    fn parse_at<'a>(
+        &self,
+        input: &'a I,
+        offset: usize,
+    ) -> ParseResult<'a, AbstractSyntaxTree<&'a I>, I> {
+        match self.parser.parse_at(input, offset) {
+            Ok((remaining, _)) => Ok((
+                remaining,
+                AbstractSyntaxTree::Ignore(Span::consumed(input, remaining, offset)),
+            )),
             Err(e) => Err(e),
         }
     }
 
     fn get_name_clone(&self) -> String {
-        self.name.clone()
+        self.name.to_string()
+    }
+
+    fn with_wrapped(&self, f: &mut dyn FnMut(&dyn Parser<I>)) {
+        f(K::as_dyn(&self.parser))
     }
 }
 
-impl Ignore {
-    pub fn new(parser: Rc<dyn Parser>) -> Ignore {
+impl<I: Input + ?Sized, K: Sharing> Ignore<I, K> {
+    pub fn new(parser: K::Parser<I>) -> Ignore<I, K> {
         Ignore {
-            name: format!("ignore_{}", parser.get_name_clone()),
+            name: format!("ignore_{}", parser.get_name_clone()).into(),
             parser,
         }
     }
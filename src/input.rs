@@ -0,0 +1,68 @@
+use std::fmt::Debug;
+
+/// Inputs that parsers can consume: `str`, or slices of any element type such as `[u8]`,
+/// `[char]` or tokens produced by a separate lexer.
+///
+/// Positions (offsets, spans) are measured in *units* of the input: bytes for `str`, elements for slices.
+/// Leaves of the abstract syntax tree borrow the matched input (`&str`, `&[T]`), and can be turned
+/// into the owned form (`String`, `Vec<T>`) with `AbstractSyntaxTree::into_owned`.
+pub trait Input: ToOwned {
+    /// The elements that atoms match one at a time: `char` for `str`, `T` for `[T]`.
+    type Item;
+
+    /// Length of the input, in units.
+    fn input_len(&self) -> usize;
+
+    /// Returns the first item of the input along with its width in units, or `None` if the input is empty.
+    fn first_item(&self) -> Option<(Self::Item, usize)>;
+
+    /// Splits the input after `units` units. `units` must fall on an item boundary.
+    fn split_input_at(&self, units: usize) -> (&Self, &Self);
+
+    /// Describes an item in error messages.
+    fn describe_item(item: &Self::Item) -> String;
+
+    fn is_input_empty(&self) -> bool {
+        self.input_len() == 0
+    }
+}
+
+impl Input for str {
+    type Item = char;
+
+    fn input_len(&self) -> usize {
+        self.len()
+    }
+
+    fn first_item(&self) -> Option<(char, usize)> {
+        self.chars().next().map(|c| (c, c.len_utf8()))
+    }
+
+    fn split_input_at(&self, units: usize) -> (&str, &str) {
+        self.split_at(units)
+    }
+
+    fn describe_item(item: &char) -> String {
+        item.to_string()
+    }
+}
+
+impl<T: Clone + Debug> Input for [T] {
+    type Item = T;
+
+    fn input_len(&self) -> usize {
+        self.len()
+    }
+
+    fn first_item(&self) -> Option<(T, usize)> {
+        self.first().map(|item| (item.clone(), 1))
+    }
+
+    fn split_input_at(&self, units: usize) -> (&[T], &[T]) {
+        self.split_at(units)
+    }
+
+    fn describe_item(item: &T) -> String {
+        format!("{item:?}")
+    }
+}
@@ -1,8 +1,83 @@
 pub mod atoms;
+mod batch;
 mod brick;
+mod builder;
 pub mod constants;
+mod cst;
+pub mod diagnostics;
+mod dot;
+mod from_ast;
 mod ignore;
+mod input;
+mod lookahead;
+mod memo;
 mod parser;
+mod peg;
+mod pratt;
+mod query;
+mod repetition;
+mod rule;
+mod serialize;
+mod sharing;
+mod symbol;
+#[cfg(test)]
+mod tests;
+mod typed;
 pub mod utils;
+mod visit;
 
-pub use {atoms::*, brick::*, constants::*, parser::*, utils::*};
+pub use {
+    atoms::*, batch::*, brick::*, builder::*, constants::*, cst::*, diagnostics::*, from_ast::*,
+    input::*, memo::*, parser::*, pratt::*, query::*, sharing::*, symbol::*, typed::*, utils::*,
+    visit::*,
+};
+
+/// The composite parsers, generic over how they hold their sub-parsers (see `Sharing`).
+/// The root of the crate names their `Local` form, and `sync` their `ThreadSafe` form.
+pub mod generic {
+    pub use crate::brick::Brick;
+    pub use crate::builder::BrickBuilder;
+    pub use crate::ignore::Ignore;
+    pub use crate::lookahead::{Lookahead, NotFollowedBy};
+    pub use crate::memo::Memoize;
+    pub use crate::pratt::OperatorPrecedence;
+    pub use crate::repetition::Repetition;
+    pub use crate::rule::{Grammar, Rule};
+}
+
+pub type Brick<I = str> = generic::Brick<I, Local>;
+pub type BrickBuilder<I = str> = generic::BrickBuilder<I, Local>;
+pub type Ignore<I = str> = generic::Ignore<I, Local>;
+pub type Lookahead<I = str> = generic::Lookahead<I, Local>;
+pub type NotFollowedBy<I = str> = generic::NotFollowedBy<I, Local>;
+pub type Memoize<I = str> = generic::Memoize<I, Local>;
+pub type OperatorPrecedence<I = str> = generic::OperatorPrecedence<I, Local>;
+pub type Repetition<I = str> = generic::Repetition<I, Local>;
+pub type Rule<I = str> = generic::Rule<I, Local>;
+pub type Grammar<I = str> = generic::Grammar<I, Local>;
+
+/// The composite parsers in their `ThreadSafe` form, which hold their sub-parsers in `Arc`s and
+/// are `Send + Sync`, so that a grammar can be built once and used from several threads at once:
+///
+/// ```
+/// let grammar = parser::sync::Grammar::from_peg("list = [0-9]+ (~\",\" [0-9]+)* ;").unwrap();
+/// let list = grammar.get("list").unwrap();
+/// let count = std::thread::spawn(move || list.parse("1,2,3").unwrap().1.leaves().count());
+/// assert_eq!(count.join().unwrap(), 3);
+/// ```
+pub mod sync {
+    use crate::{ThreadSafe, generic};
+
+    pub type Brick<I = str> = generic::Brick<I, ThreadSafe>;
+    pub type BrickBuilder<I = str> = generic::BrickBuilder<I, ThreadSafe>;
+    pub type Ignore<I = str> = generic::Ignore<I, ThreadSafe>;
+    pub type Lookahead<I = str> = generic::Lookahead<I, ThreadSafe>;
+    pub type NotFollowedBy<I = str> = generic::NotFollowedBy<I, ThreadSafe>;
+    pub type Memoize<I = str> = generic::Memoize<I, ThreadSafe>;
+    pub type OperatorPrecedence<I = str> = generic::OperatorPrecedence<I, ThreadSafe>;
+    pub type Repetition<I = str> = generic::Repetition<I, ThreadSafe>;
+    pub type Rule<I = str> = generic::Rule<I, ThreadSafe>;
+    pub type Grammar<I = str> = generic::Grammar<I, ThreadSafe>;
+}
+#[cfg(feature = "derive")]
+pub use parser_derive::FromAst;
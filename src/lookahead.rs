@@ -0,0 +1,88 @@
+use crate::PropagatedParseError::BecauseSubparserAccepted;
+use crate::{
+    AbstractSyntaxTree, Input, Local, ParseError, ParseResult, Parser, Sharing, Span, Symbol,
+};
+
+/// Lookahead is a parser that applies the wrapped parser without consuming any input.
+/// If the wrapped parser fails, Lookahead fails and propagates the error of the wrapped parser.
+/// If the wrapped parser accepts, Lookahead accepts and outputs an empty Ignore leaf.
+/// This is useful to check what comes next before committing to an alternative.
+pub struct Lookahead<I: Input + ?Sized = str, K: Sharing = Local> {
+    name: Symbol,
+    parser: K::Parser<I>,
+}
+
+impl<I: Input + ?Sized, K: Sharing> Lookahead<I, K> {
+    pub fn new(parser: K::Parser<I>) -> Lookahead<I, K> {
+        Lookahead {
+            name: format!("lookahead_{}", parser.get_name_clone()).into(),
+            parser,
+        }
+    }
+}
+
+impl<I: Input + ?Sized, K: Sharing> Parser<I> for Lookahead<I, K> {
+    fn parse_at<'a>(
+        &self,
+        input: &'a I,
+        offset: usize,
+    ) -> ParseResult<'a, AbstractSyntaxTree<&'a I>, I> {
+        self.parser.parse_at(input, offset)?;
+        Ok((input, AbstractSyntaxTree::Ignore(Span::new(offset, offset))))
+    }
+
+    fn get_name_clone(&self) -> String {
+        self.name.to_string()
+    }
+
+    fn with_wrapped(&self, f: &mut dyn FnMut(&dyn Parser<I>)) {
+        f(K::as_dyn(&self.parser))
+    }
+}
+
+/// NotFollowedBy is a parser that accepts, without consuming any input, if and only if the wrapped
+/// parser rejects. It outputs an empty Ignore leaf.
+/// This is useful for boundaries, e.g. a keyword `if` must not be followed by a letter, so that
+/// `iffy` is read as an identifier.
+pub struct NotFollowedBy<I: Input + ?Sized = str, K: Sharing = Local> {
+    name: Symbol,
+    parser: K::Parser<I>,
+}
+
+impl<I: Input + ?Sized, K: Sharing> NotFollowedBy<I, K> {
+    pub fn new(parser: K::Parser<I>) -> NotFollowedBy<I, K> {
+        NotFollowedBy {
+            name: format!("not_{}", parser.get_name_clone()).into(),
+            parser,
+        }
+    }
+}
+
+impl<I: Input + ?Sized, K: Sharing> Parser<I> for NotFollowedBy<I, K> {
+    fn parse_at<'a>(
+        &self,
+        input: &'a I,
+        offset: usize,
+    ) -> ParseResult<'a, AbstractSyntaxTree<&'a I>, I> {
+        match self.parser.parse_at(input, offset) {
+            Ok((_, tree)) => Err(ParseError::new(
+                format!(
+                    "Parser \"{}\" accepted at offset {offset}, but it must not.",
+                    self.parser.get_name_clone()
+                ),
+                self.name.clone(),
+                offset,
+                BecauseSubparserAccepted(self.parser.get_name_clone(), Box::new(tree)),
+            )),
+            Err(_) => Ok((input, AbstractSyntaxTree::Ignore(Span::new(offset, offset)))),
+        }
+    }
+
+    fn get_name_clone(&self) -> String {
+        self.name.to_string()
+    }
+
+    fn with_wrapped(&self, f: &mut dyn FnMut(&dyn Parser<I>)) {
+        f(K::as_dyn(&self.parser))
+    }
+}
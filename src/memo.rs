@@ -0,0 +1,451 @@
+use crate::PropagatedParseError::Atomic;
+use crate::{AbstractSyntaxTree, Input, Local, ParseError, ParseResult, Parser, Sharing};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Result of a memoized parser at some offset. Trees share their children, so reusing a result
+/// clones it in constant time.
+type MemoResult<'a, I> = ParseResult<'a, AbstractSyntaxTree<&'a I>, I>;
+
+enum MemoEntry<'a, I: Input + ?Sized> {
+    Done(MemoResult<'a, I>),
+
+    /// Result of a parser involved in a left recursion, which depends on the seed of the head of the
+    /// recursion (see `Head`). `None` while the parser runs and no head is known yet.
+    Seed(MemoResult<'a, I>, Option<usize>),
+}
+
+/// A memoized parser running at some offset.
+struct Call {
+    id: usize,
+    offset: usize,
+    /// Head of the left recursion that the call is involved in, if any.
+    head: Option<usize>,
+}
+
+/// A left-recursive parser whose seed grows at some offset, and the parsers that it calls through
+/// before calling itself again (Warth et al., "Packrat Parsers Can Support Left Recursion").
+/// Only the results of those parsers depend on the seed.
+#[derive(Default)]
+struct Head {
+    involved: HashSet<usize>,
+    /// Involved parsers that have not run again since the seed last grew.
+    stale: HashSet<usize>,
+}
+
+/// Results shared by the `Memoize` parsers of a grammar, keyed by parser and offset.
+///
+/// The results only live for the duration of a parse: they are dropped when the outermost `Memoize`
+/// call returns or unwinds, so that results computed on an input are never reused on another. The
+/// root of a grammar should therefore be memoized too, otherwise results are dropped between the
+/// calls that the root makes to memoized parsers.
+///
+/// Each thread has its own results, so that a thread-safe grammar can run several parses at once.
+pub struct MemoTable<I: Input + ?Sized = str> {
+    id: TableId,
+    next_id: AtomicUsize,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+    input: PhantomData<fn(&I)>,
+}
+
+/// Identifier of a `MemoTable`, under which each thread registers the state of its parses.
+struct TableId(usize);
+
+impl Default for TableId {
+    fn default() -> TableId {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        TableId(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+thread_local! {
+    /// Parses in progress on the thread, by table: the addresses of their `ParseState`s, which live
+    /// on the stack of the outermost `Memoize` call of each parse, see `MemoTable::register`.
+    static STATES: RefCell<HashMap<usize, Vec<*const ()>>> = Default::default();
+}
+
+/// What a memoized parser does when it is called, see `ParseState::recall`.
+enum Recall<T> {
+    Hit(T),
+    Miss,
+    /// Run the parser again and record its result, because the seed it depends on grew.
+    Refresh,
+    /// Run the parser without recording its result, because it may depend on a seed that is
+    /// growing without being involved in its recursion.
+    Bypass,
+}
+
+/// Results of a parse in progress, and the memoized parsers that are running.
+struct ParseState<'a, I: Input + ?Sized> {
+    /// Input of the outermost call of the parse, which starts at unit `offset`.
+    input: &'a I,
+    offset: usize,
+    entries: HashMap<(usize, usize), MemoEntry<'a, I>>,
+    calls: Vec<Call>,
+    heads: HashMap<(usize, usize), Head>,
+    /// Head whose seed grows at each offset.
+    growing: HashMap<usize, usize>,
+}
+
+impl<'a, I: Input + ?Sized> ParseState<'a, I> {
+    fn new(input: &'a I, offset: usize) -> ParseState<'a, I> {
+        ParseState {
+            input,
+            offset,
+            entries: HashMap::new(),
+            calls: vec![],
+            heads: HashMap::new(),
+            growing: HashMap::new(),
+        }
+    }
+
+    /// Whether `input`, given at unit `offset`, is the suffix of the input of the parse that starts
+    /// there, in which case the results of the parse at `offset` are results on `input`.
+    fn is_parsing(&self, input: &I, offset: usize) -> bool {
+        let end = |input: &I| {
+            (input as *const I)
+                .cast::<u8>()
+                .wrapping_add(size_of_val(input))
+        };
+        offset >= self.offset
+            && self.input.input_len().checked_sub(offset - self.offset) == Some(input.input_len())
+            && end(input) == end(self.input)
+    }
+
+    fn recall(&mut self, id: usize, offset: usize) -> Recall<MemoResult<'a, I>> {
+        let key = (id, offset);
+        if let Some(&head) = self.growing.get(&offset)
+            && head != id
+        {
+            let head = self
+                .heads
+                .get_mut(&(head, offset))
+                .expect("Internal error. Please report.");
+            if head.stale.remove(&id) {
+                return Recall::Refresh;
+            }
+            if !head.involved.contains(&id) && !self.entries.contains_key(&key) {
+                return Recall::Bypass;
+            }
+        }
+        let head = match self.entries.get(&key) {
+            None => return Recall::Miss,
+            Some(MemoEntry::Done(_)) => None,
+            Some(MemoEntry::Seed(_, head)) => Some(*head),
+        };
+        if let Some(head) = head {
+            self.involve(key, head);
+        }
+        match &self.entries[&key] {
+            MemoEntry::Done(result) | MemoEntry::Seed(result, _) => Recall::Hit(result.clone()),
+        }
+    }
+
+    /// Records that the parser of `key` was called through the calls in progress above it, which
+    /// therefore depend on its seed. `head` is the head of its recursion if it is known.
+    fn involve(&mut self, key: (usize, usize), head: Option<usize>) {
+        let head = head.unwrap_or_else(|| {
+            let call = self
+                .calls
+                .iter_mut()
+                .rev()
+                .find(|call| (call.id, call.offset) == key)
+                .expect("Internal error. Please report.");
+            *call.head.get_or_insert(key.0)
+        });
+        let involved = &mut self.heads.entry((head, key.1)).or_default().involved;
+        for call in self.calls.iter_mut().rev() {
+            if call.head == Some(head) {
+                break;
+            }
+            call.head = Some(head);
+            involved.insert(call.id);
+        }
+    }
+}
+
+/// State of the parse that a `Memoize` call belongs to, see `MemoTable::state`.
+struct StateRef<'a, I: Input + ?Sized>(*const RefCell<ParseState<'a, I>>);
+
+impl<I: Input + ?Sized> Clone for StateRef<'_, I> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<I: Input + ?Sized> Copy for StateRef<'_, I> {}
+
+impl<'a, I: Input + ?Sized> StateRef<'a, I> {
+    /// Runs `f` on the state. `f` must not run parsers.
+    fn with<T>(self, f: impl FnOnce(&mut ParseState<'a, I>) -> T) -> T {
+        // SAFETY: states are registered for as long as they live, see `MemoTable::register`, and
+        // only the calls that run during the outermost call that owns the state refer to it.
+        let state = unsafe { &*self.0 };
+        f(&mut state.borrow_mut())
+    }
+}
+
+/// Registration of a state in `STATES`, which is removed when the outermost call that owns the state
+/// returns or unwinds, so that the results of a parse that panicked are never reused.
+struct Registration {
+    table: usize,
+    state: *const (),
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        STATES.with(|states| {
+            let mut states = states.borrow_mut();
+            if let Some(parses) = states.get_mut(&self.table) {
+                parses.retain(|&state| state != self.state);
+                if parses.is_empty() {
+                    states.remove(&self.table);
+                }
+            }
+        });
+    }
+}
+
+impl<I: Input + ?Sized> MemoTable<I> {
+    pub fn new() -> Arc<MemoTable<I>> {
+        Arc::new(MemoTable::default())
+    }
+
+    /// Number of times a memoized result was reused.
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// Number of times a memoized parser had to run its wrapped parser.
+    pub fn misses(&self) -> usize {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    /// Returns the state of the parse in progress on the current thread that a call on `input` at
+    /// unit `offset` belongs to, if any.
+    fn state<'a>(&self, input: &'a I, offset: usize) -> Option<StateRef<'a, I>> {
+        STATES.with(|states| {
+            let states = states.borrow();
+            let parses = states.get(&self.id.0)?;
+            parses.iter().rev().find_map(|&state| {
+                // SAFETY: the registered states of the table are alive, see `register`, and are
+                // parses of inputs of type `I`. The state was created for a lifetime other than
+                // `'a`, which only its input and results have, but `is_parsing` checks that `input`
+                // is a suffix of its input: the results at `offset`, which borrow from that suffix,
+                // live as long as `input` does, and the results on `input` as long as its input.
+                let state = StateRef(state as *const RefCell<ParseState<'a, I>>);
+                let is_parsing = unsafe { &*state.0 }.borrow().is_parsing(input, offset);
+                is_parsing.then_some(state)
+            })
+        })
+    }
+
+    /// Registers `state` as a parse in progress on the current thread until the registration is dropped.
+    /// The registration must be dropped before `state`.
+    fn register<'a>(&self, state: &RefCell<ParseState<'a, I>>) -> Registration {
+        let state = state as *const RefCell<ParseState<'a, I>> as *const ();
+        STATES.with(|states| {
+            let mut states = states.borrow_mut();
+            states.entry(self.id.0).or_default().push(state);
+        });
+        Registration {
+            table: self.id.0,
+            state,
+        }
+    }
+}
+
+impl<I: Input + ?Sized> Default for MemoTable<I> {
+    fn default() -> MemoTable<I> {
+        MemoTable {
+            id: TableId::default(),
+            next_id: AtomicUsize::new(0),
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0),
+            input: PhantomData,
+        }
+    }
+}
+
+/// Memoize is a parser that applies the wrapped parser at most once per offset of the input during a
+/// parse, reusing its result afterwards (packrat parsing). This makes grammars that backtrack a lot,
+/// like disjunctions whose alternatives start with the same rule, run in linear time.
+/// Memoization is opt-in: only the parsers wrapped in a `Memoize` are memoized.
+///
+/// `Memoize::left_recursive` additionally supports parsers that call themselves at the offset they
+/// started from, such as `expression := expression "+" term | term`, which would otherwise recurse forever.
+pub struct Memoize<I: Input + ?Sized = str, K: Sharing = Local> {
+    id: usize,
+    parser: K::Parser<I>,
+    table: Arc<MemoTable<I>>,
+    left_recursive: bool,
+}
+
+impl<I: Input + ?Sized, K: Sharing> Memoize<I, K> {
+    pub fn new(parser: K::Parser<I>, table: &Arc<MemoTable<I>>) -> Memoize<I, K> {
+        let id = table.next_id.fetch_add(1, Ordering::Relaxed);
+        Memoize {
+            id,
+            parser,
+            table: table.clone(),
+            left_recursive: false,
+        }
+    }
+
+    /// Memoizes a left-recursive parser by growing a seed: the recursive call at the starting offset
+    /// first fails, which lets the parser match its non-recursive alternatives; the parser is then
+    /// run again, the recursive call returning the previous match, for as long as the match gets longer.
+    /// The resulting trees nest to the left, which preserves the associativity of the rules.
+    ///
+    /// The recursion may go through other parsers, as in `member := primary "." name | name` with
+    /// `primary := member`, and several parsers of the recursion may be left-recursive. The memoized
+    /// parsers that the recursion goes through run again each time the seed grows; the other results
+    /// are kept.
+    pub fn left_recursive(parser: K::Parser<I>, table: &Arc<MemoTable<I>>) -> Memoize<I, K> {
+        Memoize {
+            left_recursive: true,
+            ..Memoize::new(parser, table)
+        }
+    }
+
+    /// Grows the seed `seed` of the parser at `offset`. Each round reuses the previous seed as is, so
+    /// that growing a seed takes time linear in the number of rounds.
+    fn grow_seed<'a>(
+        &self,
+        state: StateRef<'a, I>,
+        input: &'a I,
+        offset: usize,
+        seed: MemoResult<'a, I>,
+    ) -> MemoResult<'a, I> {
+        let key = (self.id, offset);
+        let consumed = |result: &MemoResult<'a, I>| match result {
+            Ok((remaining, _)) => Some(input.input_len() - remaining.input_len()),
+            Err(_) => None,
+        };
+        let outer = state.with(|state| state.growing.insert(offset, self.id));
+        let mut best = seed;
+        loop {
+            state.with(|state| {
+                let head = state
+                    .heads
+                    .get_mut(&key)
+                    .expect("Internal error. Please report.");
+                head.stale.clone_from(&head.involved);
+            });
+            let result = self.parser.parse_at(input, offset);
+            if consumed(&result) <= consumed(&best) {
+                break;
+            }
+            state.with(|state| state.entries.insert(key, MemoEntry::Done(result.clone())));
+            best = result;
+        }
+        state.with(|state| match outer {
+            Some(outer) => state.growing.insert(offset, outer),
+            None => state.growing.remove(&offset),
+        });
+        best
+    }
+
+    fn parse_memoized<'a>(
+        &self,
+        state: StateRef<'a, I>,
+        input: &'a I,
+        offset: usize,
+    ) -> MemoResult<'a, I> {
+        let key = (self.id, offset);
+        match state.with(|state| state.recall(self.id, offset)) {
+            Recall::Hit(result) => {
+                self.table.hits.fetch_add(1, Ordering::Relaxed);
+                return result;
+            }
+            Recall::Refresh => {
+                self.table.misses.fetch_add(1, Ordering::Relaxed);
+                let result = self.parser.parse_at(input, offset);
+                state.with(|state| state.entries.insert(key, MemoEntry::Done(result.clone())));
+                return result;
+            }
+            Recall::Bypass => {
+                self.table.misses.fetch_add(1, Ordering::Relaxed);
+                return self.parser.parse_at(input, offset);
+            }
+            Recall::Miss => self.table.misses.fetch_add(1, Ordering::Relaxed),
+        };
+
+        let name = self.parser.get_name_clone();
+        state.with(|state| {
+            if self.left_recursive {
+                // Recursive calls fail until the parser matches without them.
+                let seed = ParseError::new(
+                    format!("left recursion of \"{name}\" at offset {offset} has no base case"),
+                    name,
+                    offset,
+                    Atomic(
+                        "".to_string(),
+                        "a non left-recursive alternative".to_string(),
+                    ),
+                );
+                state.entries.insert(key, MemoEntry::Seed(Err(seed), None));
+            }
+            state.calls.push(Call {
+                id: self.id,
+                offset,
+                head: None,
+            });
+        });
+        let result = self.parser.parse_at(input, offset);
+        let grow = state.with(|state| {
+            let call = state.calls.pop().expect("Internal error. Please report.");
+            let entry = result.clone();
+            match call.head {
+                // The parser called itself: its result is the first seed.
+                Some(head) if head == self.id => {
+                    state.entries.insert(key, MemoEntry::Done(entry));
+                    result.is_ok()
+                }
+                Some(head) => {
+                    state
+                        .entries
+                        .insert(key, MemoEntry::Seed(entry, Some(head)));
+                    false
+                }
+                None => {
+                    state.entries.insert(key, MemoEntry::Done(entry));
+                    false
+                }
+            }
+        });
+        if grow {
+            self.grow_seed(state, input, offset, result)
+        } else {
+            result
+        }
+    }
+}
+
+impl<I: Input + ?Sized, K: Sharing> Parser<I> for Memoize<I, K> {
+    fn parse_at<'a>(
+        &self,
+        input: &'a I,
+        offset: usize,
+    ) -> ParseResult<'a, AbstractSyntaxTree<&'a I>, I> {
+        if let Some(state) = self.table.state(input, offset) {
+            return self.parse_memoized(state, input, offset);
+        }
+        // Outermost call of a parse: the state of the parse lives until it returns or unwinds.
+        let state = RefCell::new(ParseState::new(input, offset));
+        let _registration = self.table.register(&state);
+        self.parse_memoized(StateRef(&state), input, offset)
+    }
+
+    fn get_name_clone(&self) -> String {
+        self.parser.get_name_clone()
+    }
+
+    fn with_wrapped(&self, f: &mut dyn FnMut(&dyn Parser<I>)) {
+        f(K::as_dyn(&self.parser))
+    }
+}
@@ -1,13 +1,129 @@
-use crate::ParseResult;
+use crate::{AnyBrick, ConcreteSyntaxTree, Input, OwnedParseResult, ParseResult, Symbol};
+use std::ops::Range;
+use std::sync::Arc;
+
+/// A parser over inputs of type `I`, which defaults to `str`.
+pub trait Parser<I: Input + ?Sized = str> {
+    /// Parses a prefix of `input`, which is assumed to start at unit `offset` of the input given to
+    /// the outermost parser. Spans in the returned tree are expressed relative to that input.
+    /// Leaves of the returned tree borrow from `input`.
+    fn parse_at<'a>(
+        &self,
+        input: &'a I,
+        offset: usize,
+    ) -> ParseResult<'a, AbstractSyntaxTree<&'a I>, I>;
+
+    fn parse<'a>(&self, input: &'a I) -> ParseResult<'a, AbstractSyntaxTree<&'a I>, I> {
+        self.parse_at(input, 0)
+    }
+
+    /// Like `parse`, but the tree and the error are converted to their owned form, so that they can outlive the input.
+    fn parse_owned<'a>(&self, input: &'a I) -> OwnedParseResult<'a, I> {
+        match self.parse(input) {
+            Ok((remaining, tree)) => Ok((remaining, tree.into_owned())),
+            Err(e) => Err(e.into_owned()),
+        }
+    }
+
+    /// Like `parse`, but returns the lossless tree of the match, in which the ignored input is kept
+    /// as trivia (see `ConcreteSyntaxTree`).
+    fn parse_lossless<'a>(&self, input: &'a I) -> ParseResult<'a, ConcreteSyntaxTree<&'a I>, I> {
+        let (remaining, tree) = self.parse(input)?;
+        Ok((remaining, tree.to_concrete(input)))
+    }
 
-pub trait Parser {
-    fn parse<'a>(&self, input: &'a str) -> ParseResult<'a, AbstractSyntaxTree>;
     fn get_name_clone(&self) -> String;
+
+    /// Returns the parser as a brick if it is one, so that grammars can be inspected (see `Brick::to_dot`).
+    fn as_brick(&self) -> Option<&dyn AnyBrick<I>> {
+        None
+    }
+
+    /// Calls `f` on the parser wrapped by this one, for wrappers such as `Ignore` and `Rule`.
+    fn with_wrapped(&self, _f: &mut dyn FnMut(&dyn Parser<I>)) {}
+}
+
+/// Range of a node in the input of the outermost parser, in units of the input (bytes for `str`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Span {
+        Span { start, end }
+    }
+
+    /// Span of the text consumed by a parser that was given `input` at byte `offset` and left `remaining`.
+    /// `remaining` must be a suffix of `input`.
+    pub fn consumed<I: Input + ?Sized>(input: &I, remaining: &I, offset: usize) -> Span {
+        Span::new(offset, offset + input.input_len() - remaining.input_len())
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    pub fn range(&self) -> Range<usize> {
+        self.start..self.end
+    }
+}
+
+/// Output of parsers. Tags are the names of the parsers that produced the nodes, as `Symbol`s:
+/// tagging a node is a reference count increment, and comparing the tags of a grammar an integer
+/// comparison.
+/// Parsers output leaves `L = &'a I` that borrow the matched input. `into_owned` converts them to
+/// the owned form: a `String` when parsing a `str`, a `Vec<T>` when parsing a `[T]`.
+/// The children of a branch are shared, so that cloning a tree, as memoized parsers do to reuse
+/// their results, does not copy it.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum AbstractSyntaxTree<L = String> {
+    Leaf(Symbol, L, Span),
+    Branch(Symbol, Arc<[AbstractSyntaxTree<L>]>, Span),
+    Ignore(Span),
+}
+
+impl<L> AbstractSyntaxTree<L> {
+    pub fn span(&self) -> Span {
+        match self {
+            AbstractSyntaxTree::Leaf(_, _, span)
+            | AbstractSyntaxTree::Branch(_, _, span)
+            | AbstractSyntaxTree::Ignore(span) => *span,
+        }
+    }
+
+    /// Tag of the node, or `None` for an `Ignore` node.
+    pub fn tag(&self) -> Option<&str> {
+        match self {
+            AbstractSyntaxTree::Leaf(tag, _, _) | AbstractSyntaxTree::Branch(tag, _, _) => {
+                Some(tag)
+            }
+            AbstractSyntaxTree::Ignore(_) => None,
+        }
+    }
 }
 
-#[derive(Debug, PartialEq, Eq)]
-pub enum AbstractSyntaxTree {
-    Leaf(String, String),
-    Branch(String, Vec<AbstractSyntaxTree>),
-    Ignore,
+impl<I: ToOwned + ?Sized> AbstractSyntaxTree<&I> {
+    pub fn into_owned(self) -> AbstractSyntaxTree<I::Owned> {
+        match self {
+            AbstractSyntaxTree::Leaf(tag, text, span) => {
+                AbstractSyntaxTree::Leaf(tag, text.to_owned(), span)
+            }
+            AbstractSyntaxTree::Branch(tag, children, span) => AbstractSyntaxTree::Branch(
+                tag,
+                children
+                    .iter()
+                    .map(|child| child.clone().into_owned())
+                    .collect(),
+                span,
+            ),
+            AbstractSyntaxTree::Ignore(span) => AbstractSyntaxTree::Ignore(span),
+        }
+    }
 }
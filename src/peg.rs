@@ -0,0 +1,548 @@
+use crate::PropagatedParseError::Atomic;
+use crate::brick::Brick;
+use crate::ignore::Ignore;
+use crate::memo::Memoize;
+use crate::repetition::Repetition;
+use crate::rule::{Grammar, Rule};
+use crate::{
+    AllWordsFromAlphabet, CharacterFromAlphabet, MemoTable, ParseError, Share, Sharing,
+    StringParser,
+};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+/// Elements of a textual grammar, before they are compiled to parsers.
+enum Expression {
+    /// `"if"` or `'if'`.
+    Literal(String),
+    /// `[a-z_]`, or `[^a-z_]` when negated.
+    Class(HashSet<char>, bool),
+    /// The name of a rule.
+    Reference(String),
+    /// `a b c`.
+    Sequence(Vec<Expression>),
+    /// `a | b | c`.
+    Choice(Vec<Expression>),
+    /// `a*`.
+    ZeroOrMore(Box<Expression>),
+    /// `a+`.
+    OneOrMore(Box<Expression>),
+    /// `a?`.
+    Optional(Box<Expression>),
+    /// `~a`.
+    Ignored(Box<Expression>),
+}
+
+/// A rule definition read from the grammar text.
+struct Definition {
+    name: String,
+    offset: usize,
+    body: Expression,
+}
+
+/// Recursive descent reader for the textual grammar format, see `Grammar::from_peg`.
+struct GrammarReader<'a> {
+    text: &'a str,
+    position: usize,
+    references: Vec<(String, usize)>,
+}
+
+impl<'a> GrammarReader<'a> {
+    fn rest(&self) -> &'a str {
+        &self.text[self.position..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.position += c.len_utf8();
+        Some(c)
+    }
+
+    /// Skips whitespaces and `#` comments.
+    fn skip_trivia(&mut self) {
+        loop {
+            match self.peek() {
+                Some(c) if c.is_whitespace() => {
+                    self.bump();
+                }
+                Some('#') => while !matches!(self.bump(), None | Some('\n')) {},
+                _ => return,
+            }
+        }
+    }
+
+    fn error<T: ToString>(&self, expected: T) -> ParseError {
+        let expected = expected.to_string();
+        let actual = self.peek().map(String::from).unwrap_or_default();
+        let message = if actual.is_empty() {
+            format!("exhausted grammar but expected {expected}")
+        } else {
+            format!("expected {expected} but got \"{actual}\"")
+        };
+        ParseError::new(message, "grammar", self.position, Atomic(actual, expected))
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_trivia();
+        if self.peek() == Some(c) {
+            self.bump();
+            return true;
+        }
+        false
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), ParseError> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(self.error(format!("\"{c}\"")))
+        }
+    }
+
+    fn identifier(&mut self) -> Option<String> {
+        self.skip_trivia();
+        let rest = self.rest();
+        let length = rest
+            .char_indices()
+            .find(|&(i, c)| !(c == '_' || c.is_alphabetic() || (i > 0 && c.is_alphanumeric())))
+            .map_or(rest.len(), |(i, _)| i);
+        if length == 0 {
+            return None;
+        }
+        self.position += length;
+        Some(rest[..length].to_string())
+    }
+
+    /// Reads the character following a backslash.
+    fn escaped(&mut self) -> Result<char, ParseError> {
+        let c = match self.peek() {
+            Some('n') => '\n',
+            Some('t') => '\t',
+            Some('r') => '\r',
+            Some(c @ ('\\' | '"' | '\'' | ']' | '-' | '^')) => c,
+            _ => return Err(self.error("an escape sequence")),
+        };
+        self.bump();
+        Ok(c)
+    }
+
+    fn literal(&mut self, quote: char) -> Result<Expression, ParseError> {
+        let mut string = String::new();
+        loop {
+            match self.bump() {
+                None => return Err(self.error(format!("closing {quote}"))),
+                Some(c) if c == quote => break,
+                Some('\\') => string.push(self.escaped()?),
+                Some(c) => string.push(c),
+            }
+        }
+        if string.is_empty() {
+            return Err(self.error("a non-empty string"));
+        }
+        Ok(Expression::Literal(string))
+    }
+
+    fn class(&mut self) -> Result<Expression, ParseError> {
+        let mut alphabet = HashSet::new();
+        let negated = self.peek() == Some('^');
+        if negated {
+            self.bump();
+        }
+        loop {
+            let start = match self.bump() {
+                None => return Err(self.error("\"]\"")),
+                Some(']') => break,
+                Some('\\') => self.escaped()?,
+                Some(c) => c,
+            };
+            // A dash is a range operator unless it is the last character of the class.
+            if self.peek() == Some('-') && !self.rest().starts_with("-]") {
+                self.bump();
+                let end = match self.bump() {
+                    Some('\\') => self.escaped()?,
+                    Some(c) => c,
+                    None => return Err(self.error("the end of a range")),
+                };
+                if end < start {
+                    return Err(self.error("a range in increasing order"));
+                }
+                alphabet.extend(start..=end);
+            } else {
+                alphabet.insert(start);
+            }
+        }
+        if alphabet.is_empty() {
+            return Err(self.error("a non-empty class"));
+        }
+        Ok(Expression::Class(alphabet, negated))
+    }
+
+    fn primary(&mut self) -> Result<Expression, ParseError> {
+        self.skip_trivia();
+        let offset = self.position;
+        match self.peek() {
+            Some(quote @ ('"' | '\'')) => {
+                self.bump();
+                self.literal(quote)
+            }
+            Some('[') => {
+                self.bump();
+                self.class()
+            }
+            Some('(') => {
+                self.bump();
+                let expression = self.choice()?;
+                self.expect(')')?;
+                Ok(expression)
+            }
+            _ => match self.identifier() {
+                Some(name) => {
+                    self.references.push((name.clone(), offset));
+                    Ok(Expression::Reference(name))
+                }
+                None => Err(self.error("a rule name, a string, a class or \"(\"")),
+            },
+        }
+    }
+
+    fn suffixed(&mut self) -> Result<Expression, ParseError> {
+        let ignored = self.eat('~');
+        let mut expression = self.primary()?;
+        loop {
+            expression = if self.eat('*') {
+                Expression::ZeroOrMore(Box::new(expression))
+            } else if self.eat('+') {
+                Expression::OneOrMore(Box::new(expression))
+            } else if self.eat('?') {
+                Expression::Optional(Box::new(expression))
+            } else {
+                break;
+            };
+        }
+        if ignored {
+            expression = Expression::Ignored(Box::new(expression));
+        }
+        Ok(expression)
+    }
+
+    /// Whether the next token can start an element of a sequence.
+    fn at_element(&mut self) -> bool {
+        self.skip_trivia();
+        match self.peek() {
+            Some('"' | '\'' | '[' | '(' | '~') => true,
+            Some(c) => c == '_' || c.is_alphabetic(),
+            None => false,
+        }
+    }
+
+    fn sequence(&mut self) -> Result<Expression, ParseError> {
+        let mut elements = vec![self.suffixed()?];
+        while self.at_element() && !self.at_definition() {
+            elements.push(self.suffixed()?);
+        }
+        Ok(if elements.len() == 1 {
+            elements.pop().unwrap()
+        } else {
+            Expression::Sequence(elements)
+        })
+    }
+
+    fn choice(&mut self) -> Result<Expression, ParseError> {
+        let mut alternatives = vec![self.sequence()?];
+        while self.eat('|') {
+            alternatives.push(self.sequence()?);
+        }
+        Ok(if alternatives.len() == 1 {
+            alternatives.pop().unwrap()
+        } else {
+            Expression::Choice(alternatives)
+        })
+    }
+
+    /// Whether the reader is at `name =`, which starts a new definition. This lets definitions
+    /// omit their terminating semicolon.
+    fn at_definition(&mut self) -> bool {
+        let position = self.position;
+        let result = self.identifier().is_some() && self.eat('=');
+        self.position = position;
+        result
+    }
+
+    fn definitions(&mut self) -> Result<Vec<Definition>, ParseError> {
+        let mut definitions = vec![];
+        loop {
+            self.skip_trivia();
+            if self.peek().is_none() {
+                return Ok(definitions);
+            }
+            let offset = self.position;
+            let name = self.identifier().ok_or_else(|| self.error("a rule name"))?;
+            self.expect('=')?;
+            let body = self.choice()?;
+            self.eat(';');
+            definitions.push(Definition { name, offset, body });
+        }
+    }
+}
+
+/// Compiles `expression` to a parser. The outermost parser of the body of a rule is tagged with
+/// the name of the rule; inner bricks are tagged `{rule}_{kind}`.
+/// The where-clauses list the parsers it builds, which must be shareable in graphs with sharing `K`.
+fn compile<K: Sharing>(
+    expression: &Expression,
+    rule: &str,
+    is_rule_body: bool,
+    grammar: &mut Grammar<str, K>,
+) -> K::Parser<str>
+where
+    StringParser: Share<str, K>,
+    CharacterFromAlphabet<char>: Share<str, K>,
+    AllWordsFromAlphabet<char>: Share<str, K>,
+    Brick<str, K>: Share<str, K>,
+    Ignore<str, K>: Share<str, K>,
+    Repetition<str, K>: Share<str, K>,
+    Memoize<str, K>: Share<str, K>,
+    Rule<str, K>: Share<str, K>,
+{
+    // Names are interned by the grammar, so that the tags of the trees compare as integers.
+    let name = |kind: &str, grammar: &mut Grammar<str, K>| {
+        if is_rule_body {
+            grammar.symbol(rule)
+        } else {
+            grammar.symbol(&format!("{rule}_{kind}"))
+        }
+    };
+    let compile_all = |expressions: &[Expression], grammar: &mut Grammar<str, K>| {
+        expressions
+            .iter()
+            .map(|e| compile(e, rule, false, grammar))
+            .collect::<Vec<_>>()
+    };
+    match expression {
+        Expression::Literal(string) if is_rule_body => {
+            StringParser::named(grammar.symbol(rule), string).share()
+        }
+        Expression::Literal(string) => StringParser::named(grammar.symbol(string), string).share(),
+        Expression::Class(alphabet, negated) => {
+            let parser =
+                CharacterFromAlphabet::new(name("character", grammar), Arc::new(alphabet.clone()));
+            if *negated { parser.negated() } else { parser }.share()
+        }
+        Expression::Reference(target) if is_rule_body => {
+            Brick::<str, K>::new(grammar.symbol(rule), vec![grammar.rule(target)]).share()
+        }
+        Expression::Reference(target) => grammar.rule(target),
+        Expression::Sequence(elements) => {
+            Brick::<str, K>::make_linear(name("sequence", grammar), compile_all(elements, grammar))
+                .share()
+        }
+        Expression::Choice(alternatives) => Brick::<str, K>::make_disjunction(
+            name("choice", grammar),
+            compile_all(alternatives, grammar),
+        )
+        .share(),
+        // Repeated classes are matched at once by the corresponding atom.
+        Expression::ZeroOrMore(inner) | Expression::OneOrMore(inner)
+            if matches!(**inner, Expression::Class(_, false)) =>
+        {
+            let Expression::Class(alphabet, _) = &**inner else {
+                unreachable!()
+            };
+            AllWordsFromAlphabet {
+                name: name("word", grammar),
+                alphabet: Arc::new(alphabet.clone()),
+                allow_empty_word: matches!(expression, Expression::ZeroOrMore(_)),
+            }
+            .share()
+        }
+        Expression::ZeroOrMore(inner) => Repetition::<str, K>::new(
+            name("repetition", grammar),
+            compile(inner, rule, false, grammar),
+            0,
+            None,
+            None,
+        )
+        .share(),
+        Expression::OneOrMore(inner) => Repetition::<str, K>::new(
+            name("repetition", grammar),
+            compile(inner, rule, false, grammar),
+            1,
+            None,
+            None,
+        )
+        .share(),
+        Expression::Optional(inner) => Brick::<str, K>::maybe(
+            name("option", grammar),
+            compile(inner, rule, false, grammar),
+        )
+        .share(),
+        Expression::Ignored(inner) => {
+            Ignore::<str, K>::new(compile(inner, rule, false, grammar)).share()
+        }
+    }
+}
+
+/// Whether `expression` can match the empty string, knowing which rules can.
+fn nullable(expression: &Expression, nullable_rules: &HashSet<&str>) -> bool {
+    match expression {
+        Expression::Literal(_) | Expression::Class(..) => false,
+        Expression::Reference(name) => nullable_rules.contains(name.as_str()),
+        Expression::Sequence(elements) => elements.iter().all(|e| nullable(e, nullable_rules)),
+        Expression::Choice(alternatives) => {
+            alternatives.iter().any(|e| nullable(e, nullable_rules))
+        }
+        Expression::ZeroOrMore(_) | Expression::Optional(_) => true,
+        Expression::OneOrMore(inner) | Expression::Ignored(inner) => {
+            nullable(inner, nullable_rules)
+        }
+    }
+}
+
+/// Collects the rules that `expression` can call before consuming any input.
+fn leftmost_references<'e>(
+    expression: &'e Expression,
+    nullable_rules: &HashSet<&str>,
+    references: &mut Vec<&'e str>,
+) {
+    match expression {
+        Expression::Literal(_) | Expression::Class(..) => {}
+        Expression::Reference(name) => references.push(name),
+        Expression::Sequence(elements) => {
+            for element in elements {
+                leftmost_references(element, nullable_rules, references);
+                if !nullable(element, nullable_rules) {
+                    break;
+                }
+            }
+        }
+        Expression::Choice(alternatives) => {
+            for alternative in alternatives {
+                leftmost_references(alternative, nullable_rules, references);
+            }
+        }
+        Expression::ZeroOrMore(inner)
+        | Expression::OneOrMore(inner)
+        | Expression::Optional(inner)
+        | Expression::Ignored(inner) => leftmost_references(inner, nullable_rules, references),
+    }
+}
+
+/// Names of the rules that can call themselves, directly or through other rules, before consuming any input.
+fn left_recursive_rules(definitions: &[Definition]) -> HashSet<&str> {
+    let mut nullable_rules = HashSet::new();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for definition in definitions {
+            if !nullable_rules.contains(definition.name.as_str())
+                && nullable(&definition.body, &nullable_rules)
+            {
+                nullable_rules.insert(definition.name.as_str());
+                changed = true;
+            }
+        }
+    }
+
+    let leftmost: HashMap<&str, Vec<&str>> = definitions
+        .iter()
+        .map(|definition| {
+            let mut references = vec![];
+            leftmost_references(&definition.body, &nullable_rules, &mut references);
+            (definition.name.as_str(), references)
+        })
+        .collect();
+    definitions
+        .iter()
+        .map(|definition| definition.name.as_str())
+        .filter(|&name| {
+            let mut visited = HashSet::new();
+            let mut stack = leftmost[name].clone();
+            while let Some(rule) = stack.pop() {
+                if rule == name {
+                    return true;
+                }
+                if visited.insert(rule) {
+                    stack.extend(leftmost.get(rule).into_iter().flatten());
+                }
+            }
+            false
+        })
+        .collect()
+}
+
+impl<K: Sharing> Grammar<str, K>
+where
+    StringParser: Share<str, K>,
+    CharacterFromAlphabet<char>: Share<str, K>,
+    AllWordsFromAlphabet<char>: Share<str, K>,
+    Brick<str, K>: Share<str, K>,
+    Ignore<str, K>: Share<str, K>,
+    Repetition<str, K>: Share<str, K>,
+    Memoize<str, K>: Share<str, K>,
+    Rule<str, K>: Share<str, K>,
+{
+    /// Builds a grammar from a PEG-like text made of definitions `name = expression ;` (the
+    /// semicolon is optional) where expressions are made of:
+    /// - literal strings `"if"` or `'if'`, compiled to `StringParser`s;
+    /// - character classes `[a-zA-Z_]` and negated classes `[^"\n]`, compiled to `CharacterFromAlphabet`s;
+    /// - names of rules, which can be defined before or after being used;
+    /// - sequences `a b c` and ordered alternatives `a | b | c`, compiled to linear and disjunction bricks;
+    /// - repetitions `a*` and `a+`, compiled to `Repetition`s, and options `a?`; `[...]*` and `[...]+` are compiled to `AllWordsFromAlphabet`s;
+    /// - ignored elements `~a`, compiled to `Ignore`s;
+    /// - groups `(a | b)`.
+    ///
+    /// `#` starts a comment that runs until the end of the line.
+    /// The outermost node produced by a rule is tagged with the rule name.
+    ///
+    /// Left-recursive rules such as `sum = sum "+" number | number ;` are supported, including when
+    /// the recursion goes through other rules: they are wrapped in `Memoize::left_recursive`, and
+    /// produce trees that nest to the left.
+    ///
+    /// Syntax errors, undefined rules and rules defined twice are reported as `ParseError`s whose
+    /// offset points into `text`, so that they can be rendered with `ParseError::render`.
+    pub fn from_peg(text: &str) -> Result<Grammar<str, K>, ParseError> {
+        let mut reader = GrammarReader {
+            text,
+            position: 0,
+            references: vec![],
+        };
+        let definitions = reader.definitions()?;
+
+        let left_recursive = left_recursive_rules(&definitions);
+        let table = MemoTable::new();
+        let mut grammar = Grammar::new();
+        for definition in &definitions {
+            if grammar.is_defined(&definition.name) {
+                return Err(ParseError::new(
+                    format!("rule \"{}\" is defined twice", definition.name),
+                    "grammar",
+                    definition.offset,
+                    Atomic(definition.name.clone(), "a new rule name".to_string()),
+                ));
+            }
+            let mut body = compile(&definition.body, &definition.name, true, &mut grammar);
+            if left_recursive.contains(definition.name.as_str()) {
+                body = Memoize::<str, K>::left_recursive(body, &table).share();
+            }
+            grammar.define(&definition.name, body);
+        }
+
+        if let Some((name, offset)) = reader
+            .references
+            .iter()
+            .find(|(name, _)| !grammar.is_defined(name))
+        {
+            return Err(ParseError::new(
+                format!("rule \"{name}\" is used but never defined"),
+                "grammar",
+                *offset,
+                Atomic(name.clone(), "a defined rule".to_string()),
+            ));
+        }
+        Ok(grammar)
+    }
+}
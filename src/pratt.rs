@@ -0,0 +1,213 @@
+use crate::PropagatedParseError::BecauseSubparserRejected;
+use crate::parser::AbstractSyntaxTree::{Branch, Ignore};
+use crate::{
+    AbstractSyntaxTree, Input, Local, ParseError, ParseResult, Parser, Sharing, Span, Symbol,
+};
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Associativity {
+    /// `a - b - c` is `(a - b) - c`.
+    Left,
+    /// `a ^ b ^ c` is `a ^ (b ^ c)`.
+    Right,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Fixity {
+    Prefix,
+    Infix(Associativity),
+    Postfix,
+}
+
+struct Operator<I: Input + ?Sized, K: Sharing> {
+    parser: K::Parser<I>,
+    fixity: Fixity,
+    binding_power: u32,
+}
+
+impl<I: Input + ?Sized, K: Sharing> Operator<I, K> {
+    /// Binding powers of the operator on its left and on its right. They are derived from the
+    /// binding power of the operator so that operators of equal power associate in the right direction.
+    /// They are offset so that they are never 0, which is kept for the side of an operator without
+    /// an operand and for bare operands (see `operand`).
+    fn binding_powers(&self) -> (u32, u32) {
+        let power = 2 * self.binding_power + 2;
+        match self.fixity {
+            Fixity::Prefix => (0, power),
+            Fixity::Postfix => (power, 0),
+            Fixity::Infix(Associativity::Left) => (power, power + 1),
+            Fixity::Infix(Associativity::Right) => (power + 1, power),
+        }
+    }
+}
+
+/// Parses expressions made of operands and operators with precedence and associativity, using
+/// Pratt's top-down operator precedence algorithm. Operators with a higher binding power bind tighter.
+///
+/// Each operation produces a branch tagged with the name of the parser: `[operator, operand]` for
+/// prefix operators, `[left, operator, right]` for infix operators and `[operand, operator]` for
+/// postfix operators. Operands are output as is. For instance, with `+` binding less than `*`,
+/// `1+2*3` produces `expression[1, +, expression[2, *, 3]]`.
+///
+/// When several operators of the same fixity match, the one that was added first is used.
+pub struct OperatorPrecedence<I: Input + ?Sized = str, K: Sharing = Local> {
+    name: Symbol,
+    operand: K::Parser<I>,
+    operators: Vec<Operator<I, K>>,
+}
+
+impl<I: Input + ?Sized, K: Sharing> OperatorPrecedence<I, K> {
+    pub fn new<T: Into<Symbol>>(name: T, operand: K::Parser<I>) -> OperatorPrecedence<I, K> {
+        OperatorPrecedence {
+            name: name.into(),
+            operand,
+            operators: vec![],
+        }
+    }
+
+    fn operator(
+        mut self,
+        parser: K::Parser<I>,
+        fixity: Fixity,
+        binding_power: u32,
+    ) -> OperatorPrecedence<I, K> {
+        self.operators.push(Operator {
+            parser,
+            fixity,
+            binding_power,
+        });
+        self
+    }
+
+    pub fn prefix(self, parser: K::Parser<I>, binding_power: u32) -> OperatorPrecedence<I, K> {
+        self.operator(parser, Fixity::Prefix, binding_power)
+    }
+
+    pub fn infix(
+        self,
+        parser: K::Parser<I>,
+        binding_power: u32,
+        associativity: Associativity,
+    ) -> OperatorPrecedence<I, K> {
+        self.operator(parser, Fixity::Infix(associativity), binding_power)
+    }
+
+    pub fn postfix(self, parser: K::Parser<I>, binding_power: u32) -> OperatorPrecedence<I, K> {
+        self.operator(parser, Fixity::Postfix, binding_power)
+    }
+
+    /// Tries the operators of the given kind in order, returning the first that accepts along with
+    /// its output and the remaining input.
+    fn match_operator<'a>(
+        &self,
+        is_kind: impl Fn(Fixity) -> bool,
+        input: &'a I,
+        offset: usize,
+    ) -> Option<(&Operator<I, K>, &'a I, AbstractSyntaxTree<&'a I>)> {
+        self.operators
+            .iter()
+            .filter(|operator| is_kind(operator.fixity))
+            .find_map(|operator| match operator.parser.parse_at(input, offset) {
+                Ok((remaining, tree)) => Some((operator, remaining, tree)),
+                Err(_) => None,
+            })
+    }
+
+    /// Parses an expression whose operators all have a left binding power of at least `minimum`.
+    /// `input` starts at `start`, which is an offset in the outermost input.
+    fn expression<'a>(
+        &self,
+        input: &'a I,
+        start: usize,
+        minimum: u32,
+    ) -> ParseResult<'a, AbstractSyntaxTree<&'a I>, I> {
+        let position = |remaining: &I| start + input.input_len() - remaining.input_len();
+        let operation = |children: Vec<AbstractSyntaxTree<&'a I>>, remaining: &I| {
+            let children: Arc<[_]> = children
+                .into_iter()
+                .filter(|child| !matches!(child, Ignore(_)))
+                .collect();
+            Branch(
+                self.name.clone(),
+                children,
+                Span::new(start, position(remaining)),
+            )
+        };
+
+        let (mut remaining, mut left) =
+            match self.match_operator(|f| f == Fixity::Prefix, input, start) {
+                Some((operator, remaining, tree)) => {
+                    let (_, right_power) = operator.binding_powers();
+                    let (remaining, operand) =
+                        self.operand(remaining, position(remaining), right_power)?;
+                    (remaining, operation(vec![tree, operand], remaining))
+                }
+                None => self.operand(input, start, 0)?,
+            };
+
+        loop {
+            let here = position(remaining);
+            if let Some((operator, rest, tree)) =
+                self.match_operator(|f| f == Fixity::Postfix, remaining, here)
+                && operator.binding_powers().0 >= minimum
+            {
+                remaining = rest;
+                left = operation(vec![left, tree], remaining);
+                continue;
+            }
+            if let Some((operator, rest, tree)) =
+                self.match_operator(|f| matches!(f, Fixity::Infix(_)), remaining, here)
+            {
+                let (left_power, right_power) = operator.binding_powers();
+                if left_power >= minimum {
+                    let (rest, right) = self.operand(rest, position(rest), right_power)?;
+                    remaining = rest;
+                    left = operation(vec![left, tree, right], remaining);
+                    continue;
+                }
+            }
+            return Ok((remaining, left));
+        }
+    }
+
+    /// Parses the operand of an operator whose right binding power is `minimum`, wrapping errors
+    /// so that they name this parser.
+    fn operand<'a>(
+        &self,
+        input: &'a I,
+        start: usize,
+        minimum: u32,
+    ) -> ParseResult<'a, AbstractSyntaxTree<&'a I>, I> {
+        let result = if minimum == 0 {
+            self.operand.parse_at(input, start)
+        } else {
+            self.expression(input, start, minimum)
+        };
+        result.map_err(|e| {
+            ParseError::new(
+                format!(
+                    "Operator precedence parser \"{}\" expected an operand at offset {start}.",
+                    self.name
+                ),
+                self.name.clone(),
+                start,
+                BecauseSubparserRejected(Box::new(e)),
+            )
+        })
+    }
+}
+
+impl<I: Input + ?Sized, K: Sharing> Parser<I> for OperatorPrecedence<I, K> {
+    fn parse_at<'a>(
+        &self,
+        input: &'a I,
+        offset: usize,
+    ) -> ParseResult<'a, AbstractSyntaxTree<&'a I>, I> {
+        self.expression(input, offset, 0)
+    }
+
+    fn get_name_clone(&self) -> String {
+        self.name.to_string()
+    }
+}
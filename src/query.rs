@@ -0,0 +1,303 @@
+use crate::AbstractSyntaxTree::{self, Ignore, Leaf};
+use crate::PropagatedParseError::Atomic;
+use crate::{ParseError, Symbol};
+
+/// How a step of a query relates to the previous one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Combinator {
+    /// `a b`: the node is below a node matched by the previous step.
+    Descendant,
+    /// `a > b`: the node is a child of a node matched by the previous step.
+    Child,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum TextPredicate {
+    /// `[text="..."]`.
+    Equals(String),
+    /// `[text^="..."]`.
+    StartsWith(String),
+    /// `[text$="..."]`.
+    EndsWith(String),
+    /// `[text*="..."]`.
+    Contains(String),
+}
+
+impl TextPredicate {
+    fn matches(&self, text: &str) -> bool {
+        match self {
+            TextPredicate::Equals(s) => text == s,
+            TextPredicate::StartsWith(s) => text.starts_with(s.as_str()),
+            TextPredicate::EndsWith(s) => text.ends_with(s.as_str()),
+            TextPredicate::Contains(s) => text.contains(s.as_str()),
+        }
+    }
+}
+
+/// Conditions on a single node, e.g. `identifier:nth(0)[text^="_"]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Step {
+    combinator: Combinator,
+    /// `None` for `*`.
+    tag: Option<Symbol>,
+    nth: Option<usize>,
+    text: Vec<TextPredicate>,
+}
+
+/// Text of a node: the text of a leaf, or the concatenated texts of the leaves below a branch.
+fn text<L: AsRef<str>>(node: &AbstractSyntaxTree<L>) -> String {
+    node.leaves()
+        .map(|leaf| match leaf {
+            Leaf(_, text, _) => text.as_ref(),
+            _ => "",
+        })
+        .collect()
+}
+
+impl Step {
+    /// Whether `node`, which is child number `index` of its parent, satisfies the step.
+    fn matches<L: AsRef<str>>(&self, node: &AbstractSyntaxTree<L>, index: Option<usize>) -> bool {
+        if matches!(node, Ignore(_)) {
+            return false;
+        }
+        if let Some(tag) = &self.tag
+            && node.symbol() != Some(tag)
+        {
+            return false;
+        }
+        if self.nth.is_some() && self.nth != index {
+            return false;
+        }
+        if !self.text.is_empty() {
+            let text = text(node);
+            return self.text.iter().all(|predicate| predicate.matches(&text));
+        }
+        true
+    }
+}
+
+/// A compiled selector over the tags of a tree, in the spirit of CSS selectors:
+/// - `function` matches the nodes tagged `function`, and `*` matches any node;
+/// - `function identifier` matches the `identifier` nodes below a `function` node, and
+///   `call > identifier` the `identifier` nodes that are children of a `call` node;
+/// - `argument:nth(0)` matches the `argument` nodes that are the first child of their parent;
+/// - `identifier[text="main"]` matches the `identifier` nodes whose text is `main`, with `^=`,
+///   `$=` and `*=` for prefixes, suffixes and substrings. The text of a branch is the
+///   concatenated text of its leaves.
+///
+/// ```
+/// # use parser::{Grammar, Parser, Query};
+/// let grammar = Grammar::from_peg(r#"
+///     call       = identifier ~"(" identifier ~")" ;
+///     identifier = [a-z]+ ;
+/// "#).unwrap();
+/// let (_, tree) = grammar.get("call").unwrap().parse("print(x)").unwrap();
+/// let query = Query::new("call > identifier:nth(1)").unwrap();
+/// assert_eq!(query.select(&tree)[0].span().start, 6);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Query {
+    steps: Vec<Step>,
+}
+
+impl Query {
+    pub fn new(selector: &str) -> Result<Query, ParseError> {
+        let mut reader = QueryReader {
+            text: selector,
+            position: 0,
+        };
+        let mut steps = vec![reader.step(Combinator::Descendant)?];
+        loop {
+            let spaced = reader.skip_whitespace();
+            if reader.peek().is_none() {
+                break;
+            }
+            let combinator = if reader.eat('>') {
+                Combinator::Child
+            } else if spaced {
+                Combinator::Descendant
+            } else {
+                return Err(reader.error("a combinator"));
+            };
+            steps.push(reader.step(combinator)?);
+        }
+        Ok(Query { steps })
+    }
+
+    /// Returns the nodes of `tree`, including its root, that match the query, in pre-order.
+    pub fn select<'t, L: AsRef<str>>(
+        &self,
+        tree: &'t AbstractSyntaxTree<L>,
+    ) -> Vec<&'t AbstractSyntaxTree<L>> {
+        let mut selected = vec![];
+        let mut path = vec![];
+        self.select_in(tree, None, &mut path, &mut selected);
+        selected
+    }
+
+    fn select_in<'t, L: AsRef<str>>(
+        &self,
+        node: &'t AbstractSyntaxTree<L>,
+        index: Option<usize>,
+        path: &mut Vec<(&'t AbstractSyntaxTree<L>, Option<usize>)>,
+        selected: &mut Vec<&'t AbstractSyntaxTree<L>>,
+    ) {
+        path.push((node, index));
+        if self.matches_at(path, self.steps.len() - 1, path.len() - 1) {
+            selected.push(node);
+        }
+        for (i, child) in node.children().iter().enumerate() {
+            self.select_in(child, Some(i), path, selected);
+        }
+        path.pop();
+    }
+
+    /// Whether the node at `path[position]` matches the steps up to `step`, its ancestors being
+    /// the nodes before it in `path`.
+    fn matches_at<L: AsRef<str>>(
+        &self,
+        path: &[(&AbstractSyntaxTree<L>, Option<usize>)],
+        step: usize,
+        position: usize,
+    ) -> bool {
+        let (node, index) = path[position];
+        if !self.steps[step].matches(node, index) {
+            return false;
+        }
+        if step == 0 {
+            return true;
+        }
+        match self.steps[step].combinator {
+            Combinator::Child => position > 0 && self.matches_at(path, step - 1, position - 1),
+            Combinator::Descendant => {
+                (0..position).any(|ancestor| self.matches_at(path, step - 1, ancestor))
+            }
+        }
+    }
+}
+
+/// Reader for the selector syntax, see `Query`.
+struct QueryReader<'a> {
+    text: &'a str,
+    position: usize,
+}
+
+impl QueryReader<'_> {
+    fn peek(&self) -> Option<char> {
+        self.text[self.position..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.position += c.len_utf8();
+        Some(c)
+    }
+
+    /// Skips whitespaces and returns whether there were any.
+    fn skip_whitespace(&mut self) -> bool {
+        let start = self.position;
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.bump();
+        }
+        self.position > start
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.bump();
+            return true;
+        }
+        false
+    }
+
+    fn error<T: ToString>(&self, expected: T) -> ParseError {
+        let expected = expected.to_string();
+        let actual = self.peek().map(String::from).unwrap_or_default();
+        let message = if actual.is_empty() {
+            format!("exhausted query but expected {expected}")
+        } else {
+            format!("expected {expected} but got \"{actual}\"")
+        };
+        ParseError::new(message, "query", self.position, Atomic(actual, expected))
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), ParseError> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(self.error(format!("\"{c}\"")))
+        }
+    }
+
+    fn take_while(&mut self, predicate: impl Fn(char) -> bool) -> &str {
+        let start = self.position;
+        while self.peek().is_some_and(&predicate) {
+            self.bump();
+        }
+        &self.text[start..self.position]
+    }
+
+    fn step(&mut self, combinator: Combinator) -> Result<Step, ParseError> {
+        self.skip_whitespace();
+        let tag = if self.eat('*') {
+            None
+        } else {
+            let tag = self.take_while(|c| c == '_' || c.is_alphanumeric());
+            if tag.is_empty() {
+                return Err(self.error("a tag or \"*\""));
+            }
+            Some(Symbol::new(tag))
+        };
+        let mut step = Step {
+            combinator,
+            tag,
+            nth: None,
+            text: vec![],
+        };
+        loop {
+            if self.eat(':') {
+                if self.take_while(char::is_alphabetic) != "nth" {
+                    return Err(self.error("\"nth\""));
+                }
+                self.expect('(')?;
+                let index = self.take_while(|c| c.is_ascii_digit());
+                step.nth = Some(index.parse().map_err(|_| self.error("an index"))?);
+                self.expect(')')?;
+            } else if self.eat('[') {
+                if self.take_while(char::is_alphabetic) != "text" {
+                    return Err(self.error("\"text\""));
+                }
+                let operator = self.take_while(|c| "^$*".contains(c)).to_string();
+                self.expect('=')?;
+                let string = self.string()?;
+                step.text.push(match operator.as_str() {
+                    "" => TextPredicate::Equals(string),
+                    "^" => TextPredicate::StartsWith(string),
+                    "$" => TextPredicate::EndsWith(string),
+                    "*" => TextPredicate::Contains(string),
+                    _ => return Err(self.error("\"=\", \"^=\", \"$=\" or \"*=\"")),
+                });
+                self.expect(']')?;
+            } else {
+                return Ok(step);
+            }
+        }
+    }
+
+    /// Reads a double-quoted string, where `\"` and `\\` stand for `"` and `\`.
+    fn string(&mut self) -> Result<String, ParseError> {
+        self.expect('"')?;
+        let mut string = String::new();
+        loop {
+            match self.bump() {
+                None => return Err(self.error("closing \"")),
+                Some('"') => return Ok(string),
+                Some('\\') => match self.bump() {
+                    Some(c @ ('"' | '\\')) => string.push(c),
+                    _ => return Err(self.error("an escape sequence")),
+                },
+                Some(c) => string.push(c),
+            }
+        }
+    }
+}
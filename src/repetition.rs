@@ -0,0 +1,137 @@
+use crate::PropagatedParseError::BecauseSubparserRejected;
+use crate::parser::AbstractSyntaxTree::{Branch, Ignore};
+use crate::{
+    AbstractSyntaxTree, Input, Local, ParseError, ParseResult, Parser, Sharing, Span, Symbol,
+};
+
+/// Repetition is a parser that applies the wrapped parser between `min` and `max` times, or at
+/// least `min` times if `max` is `None`. Elements are separated by elements matched by
+/// `separator`, if any. It outputs a branch that contains the elements and the separators, in order.
+///
+/// Repetitions are greedy: they match as many elements as possible, and reject if they cannot
+/// match `min` of them. A separator that is not followed by an element is not consumed.
+/// Once `min` elements are matched, the repetition stops as soon as the wrapped parser accepts
+/// without consuming input, since it would otherwise loop forever.
+///
+/// Elements are counted rather than unrolled into states, so bounds cost nothing, and each element
+/// and separator is parsed once.
+pub struct Repetition<I: Input + ?Sized = str, K: Sharing = Local> {
+    name: Symbol,
+    parser: K::Parser<I>,
+    min: usize,
+    max: Option<usize>,
+    separator: Option<K::Parser<I>>,
+}
+
+impl<I: Input + ?Sized, K: Sharing> Repetition<I, K> {
+    pub fn new<T: Into<Symbol>>(
+        name: T,
+        parser: K::Parser<I>,
+        min: usize,
+        max: Option<usize>,
+        separator: Option<K::Parser<I>>,
+    ) -> Repetition<I, K> {
+        match max {
+            Some(0) => {
+                panic!("Tried to create a repetition of at most 0 elements. This is illegal.")
+            }
+            Some(max) if max < min => panic!(
+                "Tried to create a repetition of at least {min} and at most {max} elements. This is illegal."
+            ),
+            _ => {}
+        }
+        Repetition {
+            name: name.into(),
+            parser,
+            min,
+            max,
+            separator,
+        }
+    }
+
+    /// Error of a repetition that stopped at `position` because of `error` before matching `min` elements.
+    fn reject<'a>(
+        &self,
+        error: ParseError<&'a I>,
+        offset: usize,
+        position: usize,
+    ) -> ParseError<&'a I> {
+        let committed = error.committed;
+        let mut error = ParseError::new(
+            format!(
+                "Repetition \"{}\" starting at offset {offset} failed at offset {position}.",
+                self.name
+            ),
+            self.name.clone(),
+            position,
+            BecauseSubparserRejected(Box::new(error)),
+        );
+        error.committed = committed;
+        error
+    }
+}
+
+impl<I: Input + ?Sized, K: Sharing> Parser<I> for Repetition<I, K> {
+    fn parse_at<'a>(
+        &self,
+        input: &'a I,
+        offset: usize,
+    ) -> ParseResult<'a, AbstractSyntaxTree<&'a I>, I> {
+        let position = |remaining: &I| offset + input.input_len() - remaining.input_len();
+        let mut remaining = input;
+        let mut results: Vec<AbstractSyntaxTree<&'a I>> = vec![];
+        let mut count = 0;
+        while self.max != Some(count) {
+            // The separator and the element are only kept if both accept.
+            let mut rest = remaining;
+            let mut separator_tree = None;
+            if count > 0
+                && let Some(separator) = &self.separator
+            {
+                match separator.parse_at(rest, position(rest)) {
+                    Ok((after, tree)) => {
+                        rest = after;
+                        separator_tree = Some(tree);
+                    }
+                    Err(e) if count >= self.min && !e.committed => break,
+                    Err(e) => return Err(self.reject(e, offset, position(rest))),
+                }
+            }
+            match self.parser.parse_at(rest, position(rest)) {
+                Ok((after, _))
+                    if count >= self.min && after.input_len() == remaining.input_len() =>
+                {
+                    break;
+                }
+                Ok((after, tree)) => {
+                    results.extend(separator_tree);
+                    results.push(tree);
+                    remaining = after;
+                    count += 1;
+                }
+                Err(e) if count >= self.min && !e.committed => break,
+                Err(e) => return Err(self.reject(e, offset, position(rest))),
+            }
+        }
+        let results = results
+            .into_iter()
+            .filter(|result| !matches!(result, Ignore(_)))
+            .collect();
+        Ok((
+            remaining,
+            Branch(
+                self.name.clone(),
+                results,
+                Span::consumed(input, remaining, offset),
+            ),
+        ))
+    }
+
+    fn get_name_clone(&self) -> String {
+        self.name.to_string()
+    }
+
+    fn with_wrapped(&self, f: &mut dyn FnMut(&dyn Parser<I>)) {
+        f(K::as_dyn(&self.parser))
+    }
+}
@@ -0,0 +1,181 @@
+use crate::PropagatedParseError::Atomic;
+use crate::utils::describe_first_item;
+use crate::{
+    AbstractSyntaxTree, Input, Interner, Local, ParseError, ParseResult, Parser, Share, Sharing,
+    Symbol,
+};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// A rule is a named placeholder for a parser that is defined later, which makes recursive and
+/// mutually recursive grammars expressible: a rule can be used inside bricks before its body is built.
+/// Parsing with a rule parses with its body, whose output is returned unchanged.
+///
+/// A rule only holds a weak reference to its body, so that a body that refers to its own rule does
+/// not form a reference cycle. Bodies are kept alive by the `Grammar` in which they were defined,
+/// and by the rules returned by `Grammar::get`. A rule whose body is gone fails to parse.
+pub struct Rule<I: Input + ?Sized = str, K: Sharing = Local> {
+    name: Symbol,
+    body: Arc<OnceLock<K::Weak<I>>>,
+
+    /// Bodies of the grammar, only held by the rules returned by `Grammar::get`: rules inside the
+    /// grammar cannot hold them, or a body referring to its own rule would never be freed.
+    _bodies: Option<Arc<Bodies<I, K>>>,
+}
+
+type Bodies<I, K> = Mutex<Vec<<K as Sharing>::Parser<I>>>;
+
+impl<I: Input + ?Sized, K: Sharing> Clone for Rule<I, K> {
+    fn clone(&self) -> Self {
+        Rule {
+            name: self.name.clone(),
+            body: self.body.clone(),
+            _bodies: self._bodies.clone(),
+        }
+    }
+}
+
+impl<I: Input + ?Sized, K: Sharing> Rule<I, K> {
+    pub fn is_defined(&self) -> bool {
+        self.body.get().is_some()
+    }
+}
+
+impl<I: Input + ?Sized, K: Sharing> Parser<I> for Rule<I, K> {
+    fn parse_at<'a>(
+        &self,
+        input: &'a I,
+        offset: usize,
+    ) -> ParseResult<'a, AbstractSyntaxTree<&'a I>, I> {
+        let body = match self.body.get() {
+            None => Err("was used before being defined"),
+            Some(body) => K::upgrade(body).ok_or("was used after its grammar was dropped"),
+        };
+        match body {
+            Ok(body) => body.parse_at(input, offset),
+            Err(reason) => {
+                let mut error = ParseError::new(
+                    format!("Rule \"{}\" {reason}.", self.name),
+                    self.name.clone(),
+                    offset,
+                    Atomic(describe_first_item(input), "a defined rule".to_string()),
+                );
+                // No alternative can make up for a missing rule.
+                error.committed = true;
+                Err(error)
+            }
+        }
+    }
+
+    fn get_name_clone(&self) -> String {
+        self.name.to_string()
+    }
+
+    fn with_wrapped(&self, f: &mut dyn FnMut(&dyn Parser<I>)) {
+        if let Some(body) = self.body.get().and_then(K::upgrade) {
+            f(K::as_dyn(&body))
+        }
+    }
+}
+
+/// A grammar owns the bodies of a set of rules, and interns the names of its rules and parsers.
+/// Rules are declared with `Grammar::rule`, which can be called before or after `Grammar::define`.
+pub struct Grammar<I: Input + ?Sized = str, K: Sharing = Local> {
+    rules: HashMap<Symbol, Rule<I, K>>,
+    bodies: Arc<Bodies<I, K>>,
+    interner: Interner,
+}
+
+impl<I: Input + ?Sized, K: Sharing> Grammar<I, K> {
+    pub fn new() -> Grammar<I, K> {
+        Grammar {
+            rules: HashMap::new(),
+            bodies: Arc::new(Mutex::new(vec![])),
+            interner: Interner::new(),
+        }
+    }
+
+    /// Returns the symbol of `text` interned by the grammar, to name the parsers of its rules: the
+    /// tags of the trees they produce are then compared as integers.
+    pub fn symbol(&mut self, text: &str) -> Symbol {
+        self.interner.intern(text)
+    }
+
+    fn declare<T: ToString>(&mut self, name: T) -> &Rule<I, K> {
+        let name = self.symbol(&name.to_string());
+        self.rules.entry(name.clone()).or_insert_with(|| Rule {
+            name,
+            body: Arc::new(OnceLock::new()),
+            _bodies: None,
+        })
+    }
+
+    /// Whether the rule called `name` was declared and defined.
+    pub(crate) fn is_defined(&self, name: &str) -> bool {
+        self.rules
+            .get(&Symbol::new(name))
+            .is_some_and(Rule::is_defined)
+    }
+
+    /// Returns the rule called `name`, declaring it if needed.
+    pub fn rule<T: ToString>(&mut self, name: T) -> K::Parser<I>
+    where
+        Rule<I, K>: Share<I, K>,
+    {
+        self.declare(name).clone().share()
+    }
+
+    /// Returns the rule called `name` if it was declared. Unlike the rules returned by
+    /// `Grammar::rule`, it keeps the bodies of the grammar alive, so that it can still parse once the
+    /// grammar is dropped. It must therefore not be used inside the bodies of the grammar.
+    pub fn get(&self, name: &str) -> Option<K::Parser<I>>
+    where
+        Rule<I, K>: Share<I, K>,
+    {
+        self.rules.get(&Symbol::new(name)).map(|rule| {
+            Rule {
+                _bodies: Some(self.bodies.clone()),
+                ..rule.clone()
+            }
+            .share()
+        })
+    }
+
+    /// Defines the body of the rule called `name`, declaring the rule if needed, and returns the rule.
+    /// The grammar keeps `body` alive for as long as it lives.
+    pub fn define<T: ToString>(&mut self, name: T, body: K::Parser<I>) -> K::Parser<I>
+    where
+        Rule<I, K>: Share<I, K>,
+    {
+        let rule = self.declare(name).clone();
+        if rule.body.set(K::downgrade(&body)).is_err() {
+            panic!(
+                "Tried to define rule \"{}\" twice. This is illegal.",
+                rule.name
+            );
+        }
+        self.bodies
+            .lock()
+            .expect("Internal error. Please report.")
+            .push(body);
+        rule.share()
+    }
+
+    /// Names of the rules that were declared but never defined.
+    pub fn undefined_rules(&self) -> Vec<Symbol> {
+        let mut undefined: Vec<Symbol> = self
+            .rules
+            .values()
+            .filter(|rule| !rule.is_defined())
+            .map(|rule| rule.name.clone())
+            .collect();
+        undefined.sort();
+        undefined
+    }
+}
+
+impl<I: Input + ?Sized, K: Sharing> Default for Grammar<I, K> {
+    fn default() -> Self {
+        Grammar::new()
+    }
+}
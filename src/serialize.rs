@@ -0,0 +1,288 @@
+use crate::AbstractSyntaxTree::{self, Branch, Ignore, Leaf};
+use crate::PropagatedParseError::Atomic;
+use crate::{ParseError, Span, Symbol};
+use std::fmt::Write;
+
+/// Whether `tag` can be written without quotes in an S-expression.
+fn is_symbol(tag: &str) -> bool {
+    !tag.is_empty()
+        && tag
+            .chars()
+            .all(|c| c.is_alphanumeric() || "_-.:".contains(c))
+}
+
+fn write_string(output: &mut String, text: &str) {
+    output.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            '\t' => output.push_str("\\t"),
+            '\r' => output.push_str("\\r"),
+            c => output.push(c),
+        }
+    }
+    output.push('"');
+}
+
+impl<L: AsRef<str>> AbstractSyntaxTree<L> {
+    /// Writes the tree as an S-expression: `(tag @start..end "text")` for a leaf,
+    /// `(tag @start..end children...)` for a branch and `(@start..end)` for an `Ignore` node.
+    /// Tags that are not made of alphanumeric characters and `_-.:` are quoted.
+    pub fn to_sexp(&self) -> String {
+        let mut output = String::new();
+        self.write_sexp(&mut output);
+        output
+    }
+
+    fn write_sexp(&self, output: &mut String) {
+        output.push('(');
+        if let Some(tag) = self.tag() {
+            if is_symbol(tag) {
+                output.push_str(tag);
+            } else {
+                write_string(output, tag);
+            }
+            output.push(' ');
+        }
+        let span = self.span();
+        let _ = write!(output, "@{}..{}", span.start, span.end);
+        match self {
+            Leaf(_, text, _) => {
+                output.push(' ');
+                write_string(output, text.as_ref());
+            }
+            Branch(_, children, _) => {
+                for child in children.iter() {
+                    output.push(' ');
+                    child.write_sexp(output);
+                }
+            }
+            Ignore(_) => {}
+        }
+        output.push(')');
+    }
+}
+
+impl AbstractSyntaxTree {
+    /// Reads a tree written by `AbstractSyntaxTree::to_sexp`. Spans may be omitted, in which case
+    /// they are empty spans at offset 0. Trees nested more than 128 levels deep are
+    /// rejected, so that untrusted input cannot overflow the stack.
+    pub fn from_sexp(text: &str) -> Result<AbstractSyntaxTree, ParseError> {
+        let mut reader = SexpReader {
+            text,
+            position: 0,
+            depth: 0,
+        };
+        let tree = reader.node()?;
+        reader.skip_whitespace();
+        if reader.peek().is_some() {
+            return Err(reader.error("end of input"));
+        }
+        Ok(tree)
+    }
+}
+
+/// Maximum depth of the trees read by `AbstractSyntaxTree::from_sexp`, the same as the recursion
+/// limit of `serde_json`.
+const MAX_SEXP_DEPTH: usize = 128;
+
+/// Reader for the format of `AbstractSyntaxTree::to_sexp`.
+struct SexpReader<'a> {
+    text: &'a str,
+    position: usize,
+    /// Number of nodes being read.
+    depth: usize,
+}
+
+impl SexpReader<'_> {
+    fn peek(&self) -> Option<char> {
+        self.text[self.position..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.position += c.len_utf8();
+        Some(c)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.bump();
+        }
+    }
+
+    fn error<T: ToString>(&self, expected: T) -> ParseError {
+        let expected = expected.to_string();
+        let actual = self.peek().map(String::from).unwrap_or_default();
+        let message = if actual.is_empty() {
+            format!("exhausted S-expression but expected {expected}")
+        } else {
+            format!("expected {expected} but got \"{actual}\"")
+        };
+        ParseError::new(message, "sexp", self.position, Atomic(actual, expected))
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), ParseError> {
+        self.skip_whitespace();
+        if self.peek() == Some(c) {
+            self.bump();
+            Ok(())
+        } else {
+            Err(self.error(format!("\"{c}\"")))
+        }
+    }
+
+    fn string(&mut self) -> Result<String, ParseError> {
+        self.expect('"')?;
+        let mut string = String::new();
+        loop {
+            match self.bump() {
+                None => return Err(self.error("closing \"")),
+                Some('"') => return Ok(string),
+                Some('\\') => string.push(match self.bump() {
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    Some('r') => '\r',
+                    Some(c @ ('"' | '\\')) => c,
+                    _ => return Err(self.error("an escape sequence")),
+                }),
+                Some(c) => string.push(c),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<usize, ParseError> {
+        let start = self.position;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.bump();
+        }
+        self.text[start..self.position]
+            .parse()
+            .map_err(|_| self.error("a number"))
+    }
+
+    /// Reads `@start..end` if it comes next.
+    fn span(&mut self) -> Result<Span, ParseError> {
+        self.skip_whitespace();
+        if self.peek() != Some('@') {
+            return Ok(Span::default());
+        }
+        self.bump();
+        let start = self.number()?;
+        self.expect('.')?;
+        self.expect('.')?;
+        let end = self.number()?;
+        Ok(Span::new(start, end))
+    }
+
+    fn node(&mut self) -> Result<AbstractSyntaxTree, ParseError> {
+        if self.depth == MAX_SEXP_DEPTH {
+            self.skip_whitespace();
+            return Err(self.error(format!("a tree at most {MAX_SEXP_DEPTH} levels deep")));
+        }
+        self.depth += 1;
+        let node = self.node_contents();
+        self.depth -= 1;
+        node
+    }
+
+    fn node_contents(&mut self) -> Result<AbstractSyntaxTree, ParseError> {
+        self.expect('(')?;
+        self.skip_whitespace();
+        let tag = match self.peek() {
+            Some('"') => Some(self.string()?),
+            Some(c) if is_symbol(&c.to_string()) => {
+                let start = self.position;
+                while self.peek().is_some_and(|c| is_symbol(&c.to_string())) {
+                    self.bump();
+                }
+                Some(self.text[start..self.position].to_string())
+            }
+            _ => None,
+        };
+        let span = self.span()?;
+        let Some(tag) = tag else {
+            self.expect(')')?;
+            return Ok(Ignore(span));
+        };
+
+        self.skip_whitespace();
+        if self.peek() == Some('"') {
+            let text = self.string()?;
+            self.expect(')')?;
+            return Ok(Leaf(Symbol::new(&tag), text, span));
+        }
+        let mut children = vec![];
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                Some(')') => {
+                    self.bump();
+                    return Ok(Branch(Symbol::new(&tag), children.into(), span));
+                }
+                Some('(') => children.push(self.node()?),
+                _ => return Err(self.error("\"(\" or \")\"")),
+            }
+        }
+    }
+}
+
+/// Trees are serialized as maps: `{"tag": ..., "text": ..., "span": ...}` for leaves,
+/// `{"tag": ..., "children": [...], "span": ...}` for branches and `{"span": ...}` for `Ignore` nodes.
+#[cfg(feature = "serde")]
+impl<L: serde::Serialize> serde::Serialize for AbstractSyntaxTree<L> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+        let mut map = serializer.serialize_map(None)?;
+        match self {
+            Leaf(tag, text, _) => {
+                map.serialize_entry("tag", &**tag)?;
+                map.serialize_entry("text", text)?;
+            }
+            Branch(tag, children, _) => {
+                map.serialize_entry("tag", &**tag)?;
+                map.serialize_entry("children", &children[..])?;
+            }
+            Ignore(_) => {}
+        }
+        map.serialize_entry("span", &self.span())?;
+        map.end()
+    }
+}
+
+/// A serialized node, before it is known to be a leaf, a branch or an `Ignore` node.
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+#[serde(bound = "L: serde::Deserialize<'de>", deny_unknown_fields)]
+struct SerializedNode<L> {
+    tag: Option<String>,
+    text: Option<L>,
+    children: Option<Vec<AbstractSyntaxTree<L>>>,
+    #[serde(default)]
+    span: Span,
+}
+
+/// Reads the format written by `Serialize`; spans may be omitted.
+#[cfg(feature = "serde")]
+impl<'de, L: serde::Deserialize<'de>> serde::Deserialize<'de> for AbstractSyntaxTree<L> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let node = SerializedNode::<L>::deserialize(deserializer)?;
+        match (node.tag, node.text, node.children) {
+            (Some(tag), Some(text), None) => Ok(Leaf(Symbol::new(&tag), text, node.span)),
+            (Some(tag), None, children) => Ok(Branch(
+                Symbol::new(&tag),
+                children.unwrap_or_default().into(),
+                node.span,
+            )),
+            (None, None, None) => Ok(Ignore(node.span)),
+            (None, _, _) => Err(serde::de::Error::custom(
+                "a node with text or children must have a tag",
+            )),
+            (Some(tag), Some(_), Some(_)) => Err(serde::de::Error::custom(format!(
+                "node \"{tag}\" cannot have both text and children"
+            ))),
+        }
+    }
+}
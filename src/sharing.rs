@@ -0,0 +1,88 @@
+use crate::{Input, Parser};
+use std::ops::Deref;
+use std::rc::{self, Rc};
+use std::sync::{self, Arc};
+
+/// How the composite parsers of a graph (bricks, rules, wrappers...) hold their sub-parsers.
+/// `Local` graphs hold them in `Rc`s and accept any parser; `ThreadSafe` graphs hold them in `Arc`s
+/// and only accept parsers that are `Send + Sync`, so that a grammar can be built once and used
+/// from several threads at once.
+///
+/// The composite parsers are generic over their sharing (see `generic`). The names at the root of
+/// the crate are those of the `Local` graphs, and the names in `sync` those of the `ThreadSafe` ones.
+pub trait Sharing: Sized + 'static {
+    /// Pointer to a sub-parser: `Rc<dyn Parser<I>>` or `Arc<dyn Parser<I> + Send + Sync>`.
+    type Parser<I: Input + ?Sized>: Clone + Deref<Target: Parser<I>>;
+
+    /// Weak counterpart of `Parser`, held by rules.
+    type Weak<I: Input + ?Sized>;
+
+    fn as_dyn<I: Input + ?Sized>(parser: &Self::Parser<I>) -> &dyn Parser<I>;
+
+    fn downgrade<I: Input + ?Sized>(parser: &Self::Parser<I>) -> Self::Weak<I>;
+
+    fn upgrade<I: Input + ?Sized>(weak: &Self::Weak<I>) -> Option<Self::Parser<I>>;
+}
+
+/// Sharing of the graphs that live on a single thread, see `Sharing`.
+pub struct Local;
+
+/// Sharing of the graphs that can be used from several threads, see `Sharing`.
+pub struct ThreadSafe;
+
+impl Sharing for Local {
+    type Parser<I: Input + ?Sized> = Rc<dyn Parser<I>>;
+    type Weak<I: Input + ?Sized> = rc::Weak<dyn Parser<I>>;
+
+    fn as_dyn<I: Input + ?Sized>(parser: &Rc<dyn Parser<I>>) -> &dyn Parser<I> {
+        &**parser
+    }
+
+    fn downgrade<I: Input + ?Sized>(parser: &Rc<dyn Parser<I>>) -> rc::Weak<dyn Parser<I>> {
+        Rc::downgrade(parser)
+    }
+
+    fn upgrade<I: Input + ?Sized>(weak: &rc::Weak<dyn Parser<I>>) -> Option<Rc<dyn Parser<I>>> {
+        weak.upgrade()
+    }
+}
+
+impl Sharing for ThreadSafe {
+    type Parser<I: Input + ?Sized> = Arc<dyn Parser<I> + Send + Sync>;
+    type Weak<I: Input + ?Sized> = sync::Weak<dyn Parser<I> + Send + Sync>;
+
+    fn as_dyn<I: Input + ?Sized>(parser: &Arc<dyn Parser<I> + Send + Sync>) -> &dyn Parser<I> {
+        &**parser
+    }
+
+    fn downgrade<I: Input + ?Sized>(
+        parser: &Arc<dyn Parser<I> + Send + Sync>,
+    ) -> sync::Weak<dyn Parser<I> + Send + Sync> {
+        Arc::downgrade(parser)
+    }
+
+    fn upgrade<I: Input + ?Sized>(
+        weak: &sync::Weak<dyn Parser<I> + Send + Sync>,
+    ) -> Option<Arc<dyn Parser<I> + Send + Sync>> {
+        weak.upgrade()
+    }
+}
+
+/// Conversion of a parser to the pointer of a graph with sharing `K`: any parser can be shared in a
+/// `Local` graph, and `Send + Sync` parsers in a `ThreadSafe` one. Code that builds graphs of any
+/// sharing, such as `Grammar::from_peg`, requires it of the parsers it builds.
+pub trait Share<I: Input + ?Sized, K: Sharing> {
+    fn share(self) -> K::Parser<I>;
+}
+
+impl<I: Input + ?Sized, P: Parser<I> + 'static> Share<I, Local> for P {
+    fn share(self) -> Rc<dyn Parser<I>> {
+        Rc::new(self)
+    }
+}
+
+impl<I: Input + ?Sized, P: Parser<I> + Send + Sync + 'static> Share<I, ThreadSafe> for P {
+    fn share(self) -> Arc<dyn Parser<I> + Send + Sync> {
+        Arc::new(self)
+    }
+}
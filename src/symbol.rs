@@ -0,0 +1,197 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::fmt::{Debug, Display};
+use std::hash::{Hash, Hasher};
+use std::num::NonZeroU32;
+use std::ops::Deref;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering as AtomicOrdering};
+
+/// A string used for the names of parsers and the tags of the trees they produce. Cloning a symbol
+/// is a reference count increment.
+///
+/// The symbols of a grammar are interned by its `Interner`, which numbers them: two symbols of the
+/// same interner are compared by number. Other symbols, such as the names given to parsers built
+/// by hand or the tags of queries and of deserialized trees, are not interned: they are compared by
+/// the hash of their text, computed once, and then by text. Symbols with the same text are equal
+/// whether they are interned or not.
+#[derive(Clone)]
+pub struct Symbol {
+    text: Arc<str>,
+    hash: u64,
+    interned: Option<Interned>,
+}
+
+/// Number of an interned symbol in its interner.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Interned {
+    interner: NonZeroU32,
+    index: u32,
+}
+
+impl Symbol {
+    /// Returns a symbol of `text` that is not interned, see `Interner::intern` for interned ones.
+    pub fn new(text: &str) -> Symbol {
+        let mut hasher = DefaultHasher::new();
+        text.hash(&mut hasher);
+        Symbol {
+            text: text.into(),
+            hash: hasher.finish(),
+            interned: None,
+        }
+    }
+
+    /// Whether the symbol was returned by an interner.
+    pub fn is_interned(&self) -> bool {
+        self.interned.is_some()
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+}
+
+/// The symbols of a grammar, see `Grammar::symbol`. Each interner numbers its symbols, so that
+/// comparing two of them compares two integers.
+pub struct Interner {
+    id: NonZeroU32,
+    symbols: HashMap<Arc<str>, Symbol>,
+}
+
+impl Interner {
+    pub fn new() -> Interner {
+        static NEXT: AtomicU32 = AtomicU32::new(1);
+        let id = NEXT.fetch_add(1, AtomicOrdering::Relaxed);
+        Interner {
+            id: NonZeroU32::new(id).expect("Tried to create more than 2^32 interners."),
+            symbols: HashMap::new(),
+        }
+    }
+
+    /// Returns the symbol of `text`, interning it if needed.
+    pub fn intern(&mut self, text: &str) -> Symbol {
+        if let Some(symbol) = self.symbols.get(text) {
+            return symbol.clone();
+        }
+        let index = u32::try_from(self.symbols.len())
+            .expect("Tried to intern more than 2^32 symbols. This is illegal.");
+        let symbol = Symbol {
+            interned: Some(Interned {
+                interner: self.id,
+                index,
+            }),
+            ..Symbol::new(text)
+        };
+        self.symbols.insert(symbol.text.clone(), symbol.clone());
+        symbol
+    }
+
+    /// Returns the interned symbol of `text` if there is one, without interning it.
+    pub fn get(&self, text: &str) -> Option<Symbol> {
+        self.symbols.get(text).cloned()
+    }
+}
+
+impl Default for Interner {
+    fn default() -> Interner {
+        Interner::new()
+    }
+}
+
+impl PartialEq for Symbol {
+    fn eq(&self, other: &Symbol) -> bool {
+        match (self.interned, other.interned) {
+            (Some(a), Some(b)) if a.interner == b.interner => a.index == b.index,
+            _ => self.hash == other.hash && self.text == other.text,
+        }
+    }
+}
+
+impl Eq for Symbol {}
+
+impl PartialEq<str> for Symbol {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for Symbol {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+/// Symbols hash like the hash of their text that they store, which is the same whether they are
+/// interned or not.
+impl Hash for Symbol {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u64(self.hash)
+    }
+}
+
+/// Symbols are ordered by text, which is consistent with equality.
+impl Ord for Symbol {
+    fn cmp(&self, other: &Symbol) -> Ordering {
+        if self == other {
+            return Ordering::Equal;
+        }
+        self.as_str().cmp(other.as_str())
+    }
+}
+
+impl PartialOrd for Symbol {
+    fn partial_cmp(&self, other: &Symbol) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Deref for Symbol {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.text
+    }
+}
+
+impl AsRef<str> for Symbol {
+    fn as_ref(&self) -> &str {
+        &self.text
+    }
+}
+
+impl Debug for Symbol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl Display for Symbol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self.as_str(), f)
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(text: &str) -> Symbol {
+        Symbol::new(text)
+    }
+}
+
+impl From<String> for Symbol {
+    fn from(text: String) -> Symbol {
+        Symbol::new(&text)
+    }
+}
+
+impl From<&String> for Symbol {
+    fn from(text: &String) -> Symbol {
+        Symbol::new(text)
+    }
+}
+
+impl From<&Symbol> for Symbol {
+    fn from(symbol: &Symbol) -> Symbol {
+        symbol.clone()
+    }
+}
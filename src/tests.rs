@@ -1,140 +1,1847 @@
-#[cfg(test)]
-mod tests {
-    use crate::atoms::*;
-    use crate::brick::ParserIndex::{AcceptingState, Index, RejectingState};
-    use crate::brick::{Brick, ParserIndex};
-    use crate::{NUMERICS, Parser};
-    use std::collections::HashSet;
-    use std::rc::Rc;
-
-    #[test]
-    fn test_parse_all_words_from_alphabet() {
-        let parse_numbers = Rc::new(AllWordsFromAlphabet {
-            name: "numerics".to_string(),
+use crate::atoms::*;
+use crate::brick::ParserIndex::{AcceptingState, Index, RejectingState};
+use crate::brick::{BrickError, ParserIndex};
+use crate::parser::AbstractSyntaxTree::{Branch, Leaf};
+use crate::{
+    AbstractSyntaxTree, Associativity, Brick, BrickBuilder, ConcreteSyntaxTree, FromAst, Grammar,
+    Ignore, Interner, LOWERCASE_ENGLISH, LineIndex, Lookahead, MemoTable, Memoize, NUMERICS,
+    NotFollowedBy, OperatorPrecedence, ParseResult, Parser, Query, Repetition, Span, Symbol,
+    TagVisitor, Target, TypedParser,
+};
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::panic::AssertUnwindSafe;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+#[test]
+fn test_parse_all_words_from_alphabet() {
+    let parse_numbers = Rc::new(AllWordsFromAlphabet {
+        name: "numerics".into(),
+        alphabet: NUMERICS.clone(),
+        allow_empty_word: false,
+    });
+    assert!(parse_numbers.parse("123").is_ok());
+    assert!(parse_numbers.parse("+").is_err());
+    assert!(parse_numbers.parse("").is_err());
+}
+
+#[test]
+fn test_expression() {
+    // In this test, we consider a simplified version of Python's rule "Expression".
+    // An expression is recursively defined as follows:
+    // Expression: "disjunction" "if" "disjunction" "else" Expression
+    //           | "disjunction"
+    //           | "lambda"
+    // where a string in quote indicates perfect match, juxtaposition is composition,
+    // and | indicates disjunction.
+
+    // We first define atomic parsers:
+    let disjunction_string = Rc::new(StringParser::new("disjunction"));
+    let lambda = Rc::new(StringParser::new("lambda"));
+    let _if = Rc::new(StringParser::new(" if "));
+    let _else = Rc::new(StringParser::new(" else "));
+
+    // There are three bricks to an Expresion parser: the "disjunction if disjunction else" chain,
+    // the "disjunction" string and the "lambda" string.
+    // "disjunction" and "lambda" were already defined. A single object suffices.
+    let chain = Rc::new(Brick::make_linear(
+        "ternary_operator",
+        vec![
+            disjunction_string.clone(),
+            _if.clone(),
+            disjunction_string.clone(),
+            _else.clone(),
+        ],
+    ));
+    let mut expression = Brick::new(
+        "expression",
+        vec![chain.clone(), disjunction_string.clone(), lambda.clone()],
+    );
+
+    // We now need to tell the expression brick how these three parsers interact.
+    // If we successfully parsed the chain, we're not done yet: we now need to parse a new expression.
+    expression.on_success[0] = ParserIndex::Index(0);
+    // If we fail to parse the chain, it's fine: maybe the expression is just the string "disjunction".
+    expression.on_failure[0] = ParserIndex::Index(1);
+    // If we successfully parse "disjunction", we're done! Otherwise, we try to parse "lambda".
+    expression.on_success[1] = AcceptingState;
+    expression.on_failure[1] = ParserIndex::Index(2);
+    // Since lambda is the last member of the disjunction that defines expressions that we try,
+    // we accept or reject the expression if and only if lambda accepts or rejects.
+    expression.on_success[2] = AcceptingState;
+    expression.on_failure[2] = RejectingState;
+
+    // We define a parser that tries to parse a string that starts with an expression and rejects otherwise.
+    let expression = Rc::new(expression);
+    let eof = Rc::new(EndOfInputParser);
+    let mut parser = Brick::new("only_expression", vec![expression.clone(), eof.clone()]);
+    parser.on_success[0] = Index(1); // if we parse an expression, we'll look for end-of-input.
+    parser.on_failure[0] = RejectingState;
+    // eof is run if and only if an expression was successfully parsed: whether it accepts
+    // determines whether we accept the input.
+    parser.on_success[1] = AcceptingState;
+    parser.on_failure[1] = RejectingState;
+
+    assert!(parser.parse("lambda").is_ok());
+    assert!(parser.parse("disjunction").is_ok());
+    assert!(parser.parse("if").is_err());
+    assert!(parser.parse("else").is_err());
+    assert!(parser.parse("").is_err());
+    assert!(
+        parser
+            .parse("disjunction if disjunction else lambda")
+            .is_ok()
+    );
+    assert!(
+        parser
+            .parse("disjunction if disjunction else disjunction if disjunction else lambda")
+            .is_ok()
+    );
+    assert!(
+        parser
+            .parse("disjunction if disjunction else disjunction if disjunction else disjunction")
+            .is_ok()
+    );
+    assert!(parser.parse("disjunction if disjunction else").is_err());
+}
+
+#[test]
+fn test_chain_of_operations() {
+    // This tests creates a parser that matches operations like 123, 123+456, and 123+456/789.
+    let parse_numbers = Rc::new(AllWordsFromAlphabet {
+        name: "numerics".into(),
+        alphabet: NUMERICS.clone(),
+        allow_empty_word: false,
+    });
+    let operators: Arc<HashSet<char>> = Arc::new(HashSet::from_iter("+-*/".chars()));
+    let parse_operator = Rc::new(CharacterFromAlphabet::new("operator", operators));
+    let parse_operation = Rc::new(Brick::make_separated(
+        "operation".to_string(),
+        parse_numbers.clone(),
+        parse_operator.clone(),
+        false,
+    ));
+    let eof = Rc::new(EndOfInputParser);
+    let parser = Brick::make_linear("only_operation", vec![parse_operation.clone(), eof.clone()]);
+
+    assert!(parser.parse("").is_err());
+    assert!(parser.parse("+").is_err());
+    assert!(parser.parse("123").is_ok());
+    assert!(parser.parse("123+").is_err());
+    assert!(parser.parse("123+456").is_ok());
+    assert!(parser.parse("0/0").is_ok());
+    assert!(parser.parse("0/2*2").is_ok());
+    assert!(parser.parse("1").is_ok());
+    assert!(parser.parse("12+21+12/12-65").is_ok());
+    assert!(parser.parse("+12").is_err());
+    assert!(parser.parse("-12").is_err());
+    assert!(parser.parse("/").is_err());
+}
+
+#[test]
+fn test_spans() {
+    let parse_numbers = Rc::new(AllWordsFromAlphabet {
+        name: "numerics".into(),
+        alphabet: NUMERICS.clone(),
+        allow_empty_word: false,
+    });
+    let parse_operator = Rc::new(CharacterFromAlphabet::new(
+        "operator",
+        Arc::new(HashSet::from_iter("+-".chars())),
+    ));
+    let parse_operation = Brick::make_separated(
+        "operation",
+        parse_numbers.clone(),
+        parse_operator.clone(),
+        false,
+    );
+
+    let (remaining, tree) = parse_operation.parse("12+345 rest").unwrap();
+    assert_eq!(remaining, " rest");
+    assert_eq!(
+        tree,
+        Branch(
+            "operation".into(),
+            vec![
+                Leaf("numerics".into(), "12", Span::new(0, 2)),
+                Leaf("operator".into(), "+", Span::new(2, 3)),
+                Leaf("numerics".into(), "345", Span::new(3, 6)),
+            ]
+            .into(),
+            Span::new(0, 6),
+        )
+    );
+
+    // Nested parsers report positions in the outermost input, not in their own.
+    let parser = Brick::make_linear(
+        "prefixed",
+        vec![Rc::new(StringParser::new("é=")), Rc::new(parse_operation)],
+    );
+    let (_, tree) = parser.parse("é=1-2").unwrap();
+    assert_eq!(tree.span(), Span::new(0, 6));
+    let Branch(_, children, _) = tree else {
+        panic!("expected a branch");
+    };
+    assert_eq!(children[1].span(), Span::new(3, 6));
+}
+
+#[test]
+fn test_error_rendering() {
+    let parser = Brick::make_linear(
+        "assignment",
+        vec![
+            Rc::new(StringParser::new("let x =\n")),
+            Rc::new(StringParser::new("  value")),
+            Rc::new(EndOfInputParser),
+        ],
+    );
+    let source = "let x =\n  valve";
+    let error = parser.parse(source).unwrap_err();
+    assert_eq!(error.offset, 8);
+    assert_eq!(error.innermost().offset, 13);
+    assert_eq!(LineIndex::new(source).line_column(13), (2, 6));
+    assert_eq!(
+        error.render(source),
+        [
+            "error: expected \"u\" but got \"v\"",
+            " --> 2:6 (in \"  value\")",
+            "  |",
+            "2 |   valve",
+            "  |      ^ expected \"u\", found \"v\"",
+        ]
+        .join("\n")
+    );
+
+    let error = parser.parse("let x =\n  value;").unwrap_err();
+    assert!(
+        error
+            .render("let x =\n  value;")
+            .ends_with("2 |   value;\n  |        ^ expected end of input, found \";\"")
+    );
+}
+
+#[test]
+fn test_non_string_inputs() {
+    // Bytes: a magic number followed by a version made of ASCII digits.
+    let header = Brick::make_linear(
+        "header",
+        vec![
+            Rc::new(StringParser::from_slice("magic", b"GIF")),
+            Rc::new(AllWordsFromAlphabet {
+                name: "version".into(),
+                alphabet: Arc::new(HashSet::from_iter(b'0'..=b'9')),
+                allow_empty_word: false,
+            }),
+        ],
+    );
+    let (remaining, tree) = header.parse(b"GIF89a\x01").unwrap();
+    assert_eq!(remaining, b"a\x01");
+    let Branch(_, children, span) = tree else {
+        panic!("expected a branch");
+    };
+    assert_eq!(span, Span::new(0, 5));
+    assert_eq!(
+        children[1],
+        Leaf("version".into(), &b"89"[..], Span::new(3, 5))
+    );
+    assert_eq!(header.parse(b"PNG").unwrap_err().innermost().offset, 0);
+
+    // Tokens produced by a separate lexer.
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+    enum Token {
+        Number(i64),
+        Plus,
+    }
+    let numbers: HashSet<Token> = (0..10).map(Token::Number).collect();
+    let sum = Brick::make_separated(
+        "sum",
+        Rc::new(CharacterFromAlphabet::new("number", Arc::new(numbers))),
+        Rc::new(StringParser::from_slice("plus", &[Token::Plus])),
+        false,
+    );
+    let tokens = [
+        Token::Number(1),
+        Token::Plus,
+        Token::Number(2),
+        Token::Number(42),
+    ];
+    let (remaining, tree) = sum.parse(&tokens).unwrap();
+    assert_eq!(remaining, &[Token::Number(42)]);
+    assert_eq!(tree.span(), Span::new(0, 3));
+}
+
+#[test]
+fn test_zero_copy_leaves() {
+    let parse_word = AllWordsFromAlphabet {
+        name: "word".into(),
+        alphabet: LOWERCASE_ENGLISH.clone(),
+        allow_empty_word: false,
+    };
+    let input = String::from("hello world");
+    let (_, tree) = parse_word.parse(input.as_str()).unwrap();
+    let Leaf(_, text, _) = &tree else {
+        panic!("expected a leaf");
+    };
+    // The leaf is a slice of the input rather than a copy of it.
+    assert_eq!(text.as_ptr(), input.as_ptr());
+
+    let owned = {
+        let input = String::from("hello world");
+        parse_word.parse_owned(input.as_str()).unwrap().1
+    };
+    assert_eq!(owned, tree.into_owned());
+    assert_eq!(
+        owned,
+        Leaf("word".into(), "hello".to_string(), Span::new(0, 5))
+    );
+}
+
+#[test]
+fn test_recursive_rules() {
+    // Expression: Term ("+" Term)*
+    // Term:       Factor ("*" Factor)*
+    // Factor:     numerics | "(" Expression ")"
+    // Rules are used before they are defined, and Factor refers back to Expression.
+    let mut grammar = Grammar::new();
+    let expression = grammar.rule("expression");
+    let term = grammar.rule("term");
+    let factor = grammar.rule("factor");
+
+    grammar.define(
+        "expression",
+        Rc::new(Brick::make_separated(
+            "sum",
+            term.clone(),
+            Rc::new(StringParser::new("+")),
+            false,
+        )),
+    );
+    grammar.define(
+        "term",
+        Rc::new(Brick::make_separated(
+            "product",
+            factor.clone(),
+            Rc::new(StringParser::new("*")),
+            false,
+        )),
+    );
+    let parenthesized = Rc::new(Brick::make_linear(
+        "parenthesized",
+        vec![
+            Rc::new(StringParser::new("(")),
+            expression.clone(),
+            Rc::new(StringParser::new(")")),
+        ],
+    ));
+    let number = Rc::new(AllWordsFromAlphabet {
+        name: "numerics".into(),
+        alphabet: NUMERICS.clone(),
+        allow_empty_word: false,
+    });
+    grammar.define(
+        "factor",
+        Rc::new(Brick::make_disjunction(
+            "factor",
+            vec![number, parenthesized.clone()],
+        )),
+    );
+    assert!(grammar.undefined_rules().is_empty());
+
+    let parser = Brick::make_linear(
+        "only_expression",
+        vec![expression.clone(), Rc::new(EndOfInputParser)],
+    );
+    assert!(parser.parse("1").is_ok());
+    assert!(parser.parse("(1+2)*3").is_ok());
+    assert!(parser.parse("((1)+2*(3+4))").is_ok());
+    assert!(parser.parse("(1+2").is_err());
+    assert!(parser.parse("1+").is_err());
+
+    // Rules do not keep their bodies alive, so the grammar can be freed despite the recursion.
+    let weak_parenthesized = Rc::downgrade(&parenthesized);
+    drop(parenthesized);
+    drop(grammar);
+    assert!(weak_parenthesized.upgrade().is_none());
+
+    // A rule whose body is gone fails instead of parsing.
+    let error = expression.parse("1").unwrap_err();
+    assert_eq!(
+        error.message,
+        "Rule \"expression\" was used after its grammar was dropped."
+    );
+    assert!(error.committed);
+    let mut grammar = Grammar::<str>::new();
+    let error = grammar.rule("later").parse("1").unwrap_err();
+    assert_eq!(
+        error.message,
+        "Rule \"later\" was used before being defined."
+    );
+
+    // The rules returned by `Grammar::get` keep the bodies alive.
+    let mut grammar = Grammar::new();
+    grammar.define("one", Rc::new(StringParser::new("1")));
+    let one = grammar.get("one").unwrap();
+    drop(grammar);
+    assert_eq!(one.parse("1").unwrap().0, "");
+}
+
+#[test]
+fn test_grammar_from_peg() {
+    let grammar = Grammar::from_peg(
+        r#"
+        # Arithmetic expressions over integers.
+        expression = term ("+" term)* ;
+        term       = factor ("*" factor)* ;
+        factor     = number | "(" expression ")" ;
+        number     = [0-9]+ ;
+        "#,
+    )
+    .unwrap();
+    let expression = grammar.get("expression").unwrap();
+    let parser = Brick::make_linear(
+        "only_expression",
+        vec![expression, Rc::new(EndOfInputParser)],
+    );
+    assert!(parser.parse("1").is_ok());
+    assert!(parser.parse("(1+2)*3").is_ok());
+    assert!(parser.parse("(1+2").is_err());
+    assert!(parser.parse("1+").is_err());
+
+    // Rule names are used as tags.
+    let (_, tree) = grammar.get("factor").unwrap().parse("42").unwrap();
+    assert_eq!(
+        tree,
+        Branch(
+            "factor".into(),
+            vec![Leaf("number".into(), "42", Span::new(0, 2))].into(),
+            Span::new(0, 2),
+        )
+    );
+
+    // Ignored elements are parsed and discarded.
+    let grammar = Grammar::from_peg("call = name ~\"(\" name ~\")\" ; name = [a-z]+ ;").unwrap();
+    let (remaining, tree) = grammar.get("call").unwrap().parse("f(x)").unwrap();
+    assert_eq!(remaining, "");
+    assert_eq!(
+        tree,
+        Branch(
+            "call".into(),
+            vec![
+                Leaf("name".into(), "f", Span::new(0, 1)),
+                Leaf("name".into(), "x", Span::new(2, 3)),
+            ]
+            .into(),
+            Span::new(0, 4),
+        )
+    );
+
+    let error = Grammar::from_peg("a = \"x\" b ;").err().unwrap();
+    assert_eq!(error.message, "rule \"b\" is used but never defined");
+    assert_eq!(error.offset, 8);
+    let error = Grammar::from_peg("a = [z-a] ;").err().unwrap();
+    assert!(
+        error
+            .render("a = [z-a] ;")
+            .contains("expected a range in increasing order")
+    );
+}
+
+#[test]
+fn test_peg_constructs() {
+    let grammar = Grammar::from_peg(
+        r#"
+        double   = "a\"b" ;
+        single   = 'a\'b' ;
+        range    = [a-c] ;
+        set      = [+\-*/] ;
+        dash     = [a-] ;
+        negated  = [^a-c\n] ;
+        string   = ~'"' [^"]* ~'"'
+        sequence = "a" "b" "c"
+        choice   = "ab" | "a" | "b"   # ordered: "ab" is tried first
+        many     = "a"*
+        some     = ("a" | "b")+
+        option   = "a" "b"? "c"
+        ignored  = ~" "* "a" ~[ ]*
+        group    = ("a" "b")* "c" ;
+        "#,
+    )
+    .unwrap();
+    // Returns the input left by `rule`, or `None` if it rejects.
+    let remaining = |rule: &str, input: &'static str| {
+        grammar
+            .get(rule)
+            .unwrap()
+            .parse(input)
+            .ok()
+            .map(|(remaining, _)| remaining)
+    };
+
+    assert_eq!(remaining("double", "a\"b"), Some(""));
+    assert_eq!(remaining("single", "a'b"), Some(""));
+    assert_eq!(remaining("range", "b"), Some(""));
+    assert_eq!(remaining("range", "d"), None);
+    assert_eq!(remaining("set", "-"), Some(""));
+    assert_eq!(remaining("set", "/"), Some(""));
+    assert_eq!(remaining("set", ","), None);
+    assert_eq!(remaining("dash", "-"), Some(""));
+    assert_eq!(remaining("negated", "d"), Some(""));
+    assert_eq!(remaining("negated", "é"), Some(""));
+    assert_eq!(remaining("negated", "b"), None);
+    assert_eq!(remaining("negated", "\n"), None);
+    assert_eq!(remaining("negated", ""), None);
+    assert_eq!(remaining("string", "\"a b\" c"), Some(" c"));
+    assert_eq!(remaining("string", "\"\""), Some(""));
+    assert_eq!(remaining("string", "\"ab"), None);
+    assert_eq!(remaining("sequence", "abcd"), Some("d"));
+    assert_eq!(remaining("sequence", "ab"), None);
+    assert_eq!(remaining("choice", "abc"), Some("c"));
+    assert_eq!(remaining("choice", "b"), Some(""));
+    assert_eq!(remaining("choice", "c"), None);
+    assert_eq!(remaining("many", ""), Some(""));
+    assert_eq!(remaining("many", "aab"), Some("b"));
+    assert_eq!(remaining("some", "abba!"), Some("!"));
+    assert_eq!(remaining("some", "!"), None);
+    assert_eq!(remaining("option", "abc"), Some(""));
+    assert_eq!(remaining("option", "ac"), Some(""));
+    assert_eq!(remaining("option", "abbc"), None);
+    assert_eq!(remaining("ignored", "  a  b"), Some("b"));
+    assert_eq!(remaining("group", "ababc"), Some(""));
+    assert_eq!(remaining("group", "abac"), None);
+
+    // Only the leaves that are not ignored are kept, and inner nodes are tagged after the rule.
+    let (_, tree) = grammar.get("string").unwrap().parse("\"ab\"").unwrap();
+    assert_eq!(
+        tree,
+        Branch(
+            "string".into(),
+            vec![Branch(
+                "string_repetition".into(),
+                vec![
+                    Leaf("string_character".into(), "a", Span::new(1, 2)),
+                    Leaf("string_character".into(), "b", Span::new(2, 3)),
+                ]
+                .into(),
+                Span::new(1, 3),
+            )]
+            .into(),
+            Span::new(0, 4),
+        )
+    );
+    let (_, Branch(_, children, _)) = grammar.get("many").unwrap().parse("aa").unwrap() else {
+        panic!("expected a branch");
+    };
+    assert_eq!(children.len(), 2);
+
+    let error = Grammar::from_peg("a = 'x' ; a = 'y' ;").err().unwrap();
+    assert_eq!(error.message, "rule \"a\" is defined twice");
+    assert_eq!(error.offset, 10);
+    let error = Grammar::from_peg("a = 'x ;").err().unwrap();
+    assert!(error.message.contains("closing '"));
+    let error = Grammar::from_peg("a = [] ;").err().unwrap();
+    assert!(error.message.contains("a non-empty class"));
+    let error = Grammar::from_peg("a = 'x' | ;").err().unwrap();
+    assert_eq!(error.offset, 10);
+}
+
+#[test]
+fn test_brick_validation() {
+    let a = Rc::new(StringParser::new("a"));
+    let b = Rc::new(StringParser::new("b"));
+    let c = Rc::new(StringParser::new("c"));
+
+    assert!(
+        Brick::make_linear("linear", vec![a.clone(), b.clone()])
+            .validate()
+            .is_ok()
+    );
+    assert!(
+        Brick::make_separated("separated", a.clone(), b.clone(), true)
+            .validate()
+            .is_ok()
+    );
+
+    let mut brick = Brick::new("broken", vec![a.clone(), b.clone()]);
+    brick.on_failure.pop();
+    assert_eq!(
+        brick.validate().unwrap_err(),
+        vec![BrickError::LengthMismatch {
+            brick: "broken".to_string(),
+            parsers: 2,
+            on_success: 2,
+            on_failure: 1,
+        }]
+    );
+
+    // Parser 1 loops on itself and rejects, parser 2 is never reached, and on_failure[0] is out of range.
+    let errors = Brick::try_new(
+        "broken",
+        vec![a.clone(), b.clone(), c.clone()],
+        vec![Index(1), Index(1), AcceptingState],
+        vec![Index(7), RejectingState, RejectingState],
+    )
+    .err()
+    .unwrap();
+    assert_eq!(
+        errors,
+        vec![
+            BrickError::TargetOutOfRange {
+                brick: "broken".to_string(),
+                index: 0,
+                on_success: false,
+                target: 7,
+            },
+            BrickError::CannotAccept {
+                brick: "broken".to_string(),
+                index: 0,
+            },
+            BrickError::CannotAccept {
+                brick: "broken".to_string(),
+                index: 1,
+            },
+            BrickError::Unreachable {
+                brick: "broken".to_string(),
+                index: 2,
+            },
+        ]
+    );
+    assert_eq!(
+        errors[0].to_string(),
+        "brick \"broken\": on_failure[0] targets parser 7, which does not exist"
+    );
+}
+
+#[test]
+fn test_brick_builder() {
+    // The expression brick of `test_expression`, with labelled states.
+    let disjunction_string = Rc::new(StringParser::new("disjunction"));
+    let chain = Rc::new(Brick::make_linear(
+        "ternary_operator",
+        vec![
+            disjunction_string.clone(),
+            Rc::new(StringParser::new(" if ")),
+            disjunction_string.clone(),
+            Rc::new(StringParser::new(" else ")),
+        ],
+    ));
+    let expression = BrickBuilder::new("expression")
+        .state("chain", chain)
+        .state("disjunction", disjunction_string)
+        .state("lambda", Rc::new(StringParser::new("lambda")))
+        .on_success("chain", "chain")
+        .on_failure("chain", "disjunction")
+        .on_failure("disjunction", "lambda")
+        .build()
+        .unwrap();
+    assert_eq!(expression.on_success[0], Index(0));
+    assert_eq!(expression.on_failure[0], Index(1));
+    assert_eq!(expression.on_failure[1], Index(2));
+
+    let parser = Brick::make_linear(
+        "only_expression",
+        vec![Rc::new(expression), Rc::new(EndOfInputParser)],
+    );
+    assert!(
+        parser
+            .parse("disjunction if disjunction else lambda")
+            .is_ok()
+    );
+    assert!(parser.parse("disjunction if disjunction else").is_err());
+
+    let errors = BrickBuilder::<str>::new("broken")
+        .state("a", Rc::new(StringParser::new("a")))
+        .state("a", Rc::new(StringParser::new("b")))
+        .on_success("a", "c")
+        .on_failure("d", Target::Accept)
+        .build()
+        .err()
+        .unwrap();
+    assert_eq!(
+        errors,
+        vec![
+            BrickError::DuplicateLabel {
+                brick: "broken".to_string(),
+                label: "a".to_string(),
+            },
+            BrickError::UnknownLabel {
+                brick: "broken".to_string(),
+                label: "d".to_string(),
+            },
+            BrickError::UnknownLabel {
+                brick: "broken".to_string(),
+                label: "c".to_string(),
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_dot_export() {
+    let mut grammar = Grammar::new();
+    let expression = grammar.rule("expression");
+    let parenthesized = Rc::new(Brick::make_linear(
+        "parenthesized",
+        vec![
+            Rc::new(StringParser::new("(")),
+            expression.clone(),
+            Rc::new(StringParser::new(")")),
+        ],
+    ));
+    let body = Rc::new(Brick::make_disjunction(
+        "expression",
+        vec![Rc::new(StringParser::new("x")), parenthesized],
+    ));
+    grammar.define("expression", body.clone());
+
+    let dot = body.to_dot();
+    assert!(dot.starts_with("digraph \"expression\" {"));
+    // The recursion through the rule is followed once.
+    assert_eq!(dot.matches("subgraph").count(), 2);
+    assert!(dot.contains("label=\"parenthesized\";"));
+    assert!(dot.contains("b0_s0 -> b0_accept [color=darkgreen];"));
+    assert!(dot.contains("b0_s0 -> b0_s1 [color=red, style=dashed];"));
+    assert!(dot.contains("b0_s1 -> b1_start [style=dotted, arrowhead=empty];"));
+    assert!(dot.contains("b1_s1 -> b0_start [style=dotted, arrowhead=empty];"));
+    assert!(dot.ends_with("}\n"));
+}
+
+/// Wraps a parser and counts how many times it is run.
+struct Counter {
+    parser: Rc<dyn Parser>,
+    count: AtomicUsize,
+}
+
+impl Parser for Counter {
+    fn parse_at<'a>(
+        &self,
+        input: &'a str,
+        offset: usize,
+    ) -> ParseResult<'a, AbstractSyntaxTree<&'a str>> {
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.parser.parse_at(input, offset)
+    }
+
+    fn get_name_clone(&self) -> String {
+        self.parser.get_name_clone()
+    }
+}
+
+/// Parser that panics on a `!`, and otherwise matches the empty string.
+struct PanicOnBang;
+
+impl Parser for PanicOnBang {
+    fn parse_at<'a>(
+        &self,
+        input: &'a str,
+        offset: usize,
+    ) -> ParseResult<'a, AbstractSyntaxTree<&'a str>> {
+        if input.starts_with('!') {
+            panic!("Found a bang.");
+        }
+        Ok((input, AbstractSyntaxTree::Ignore(Span::new(offset, offset))))
+    }
+
+    fn get_name_clone(&self) -> String {
+        "panic_on_bang".to_string()
+    }
+}
+
+/// Checks that `parse(n)` runs in time linear in `n`: parsing eight times more must not take more
+/// than three times as long as linear time predicts (quadratic time would take eight times longer
+/// still), the best of three runs being timed to reduce noise.
+fn assert_linear(parse: impl Fn(usize)) {
+    let time = |n| {
+        (0..3)
+            .map(|_| {
+                let start = Instant::now();
+                parse(n);
+                start.elapsed()
+            })
+            .min()
+            .unwrap()
+    };
+    let (small, large) = (time(500), time(4000));
+    assert!(
+        large < small * 24,
+        "parsing 4000 items took {large:?}, against {small:?} for 500 items"
+    );
+}
+
+/// Runs `test` on a thread with a stack large enough for grammars that recurse once per item of
+/// long inputs.
+fn with_large_stack(test: impl FnOnce() + Send) {
+    std::thread::scope(|scope| {
+        std::thread::Builder::new()
+            .stack_size(64 << 20)
+            .spawn_scoped(scope, test)
+            .unwrap()
+            .join()
+            .unwrap()
+    });
+}
+
+#[test]
+fn test_memoization() {
+    let table = MemoTable::new();
+    let counter = Rc::new(Counter {
+        parser: Rc::new(StringParser::new("disjunction")),
+        count: AtomicUsize::new(0),
+    });
+    let disjunction_string = Rc::new(Memoize::new(counter.clone(), &table));
+    let ternary = Rc::new(Brick::make_linear(
+        "ternary_operator",
+        vec![
+            disjunction_string.clone(),
+            Rc::new(StringParser::new(" if ")),
+            disjunction_string.clone(),
+            Rc::new(StringParser::new(" else ")),
+            disjunction_string.clone(),
+        ],
+    ));
+    let expression = Memoize::new(
+        Rc::new(Brick::make_disjunction(
+            "expression",
+            vec![ternary, disjunction_string.clone()],
+        )),
+        &table,
+    );
+
+    // The fallback alternative reuses the result of the ternary operator's first parser.
+    let (_, tree) = expression.parse("disjunction").unwrap();
+    assert_eq!(counter.count.load(Ordering::Relaxed), 1);
+    assert_eq!(table.hits(), 1);
+    assert_eq!(
+        tree,
+        Branch(
+            "expression".into(),
+            vec![Leaf("disjunction".into(), "disjunction", Span::new(0, 11))].into(),
+            Span::new(0, 11),
+        )
+    );
+
+    // Results do not leak from one parse to the next.
+    assert!(expression.parse("lambda").is_err());
+    assert_eq!(counter.count.load(Ordering::Relaxed), 2);
+    assert!(
+        expression
+            .parse("disjunction if disjunction else disjunction")
+            .is_ok()
+    );
+    assert_eq!(counter.count.load(Ordering::Relaxed), 5);
+
+    // The results of a parse that panicked are dropped with it.
+    let guarded = Memoize::new(
+        Rc::new(Brick::make_linear(
+            "guarded",
+            vec![disjunction_string.clone(), Rc::new(PanicOnBang)],
+        )),
+        &table,
+    );
+    let panicked = std::panic::catch_unwind(AssertUnwindSafe(|| {
+        let _ = guarded.parse("disjunction!");
+    }));
+    assert!(panicked.is_err());
+    assert!(guarded.parse("lambda").is_err());
+    assert_eq!(
+        guarded.parse("disjunction").unwrap().1.span(),
+        Span::new(0, 11)
+    );
+
+    // Reusing a result does not copy its tree, so that memoized right recursion runs in linear time.
+    with_large_stack(|| {
+        let table = MemoTable::new();
+        let mut grammar = Grammar::new();
+        let item = Rc::new(AllWordsFromAlphabet {
+            name: "item".into(),
             alphabet: NUMERICS.clone(),
             allow_empty_word: false,
         });
-        assert!(parse_numbers.parse("123").is_ok());
-        assert!(parse_numbers.parse("+").is_err());
-        assert!(parse_numbers.parse("").is_err());
-    }
-
-    #[test]
-    fn test_expression() {
-        // In this test, we consider a simplified version of Python's rule "Expression".
-        // An expression is recursively defined as follows:
-        // Expression: "disjunction" "if" "disjunction" "else" Expression
-        //           | "disjunction"
-        //           | "lambda"
-        // where a string in quote indicates perfect match, juxtaposition is composition,
-        // and | indicates disjunction.
-
-        // We first define atomic parsers:
-        let disjunction_string = Rc::new(StringParser::new("disjunction"));
-        let lambda = Rc::new(StringParser::new("lambda"));
-        let _if = Rc::new(StringParser::new(" if "));
-        let _else = Rc::new(StringParser::new(" else "));
-
-        // There are three bricks to an Expresion parser: the "disjunction if disjunction else" chain,
-        // the "disjunction" string and the "lambda" string.
-        // "disjunction" and "lambda" were already defined. A single object suffices.
-        let chain = Rc::new(Brick::make_linear(
-            "ternary_operator",
+        let items = Rc::new(Brick::make_linear(
+            "items",
             vec![
-                disjunction_string.clone(),
-                _if.clone(),
-                disjunction_string.clone(),
-                _else.clone(),
+                item.clone(),
+                Rc::new(StringParser::new(",")),
+                grammar.rule("list"),
             ],
         ));
-        let mut expression = Brick::new(
-            "expression",
-            vec![chain.clone(), disjunction_string.clone(), lambda.clone()],
+        let list = grammar.define(
+            "list",
+            Rc::new(Memoize::new(
+                Rc::new(Brick::make_disjunction("list", vec![items, item])),
+                &table,
+            )),
         );
+        assert_linear(|n| {
+            let input = vec!["1"; n].join(",");
+            assert_eq!(list.parse(&input).unwrap().0, "");
+        });
+    });
+}
 
-        // We now need to tell the expression brick how these three parsers interact.
-        // If we successfully parsed the chain, we're not done yet: we now need to parse a new expression.
-        expression.on_success[0] = ParserIndex::Index(0);
-        // If we fail to parse the chain, it's fine: maybe the expression is just the string "disjunction".
-        expression.on_failure[0] = ParserIndex::Index(1);
-        // If we successfully parse "disjunction", we're done! Otherwise, we try to parse "lambda".
-        expression.on_success[1] = AcceptingState;
-        expression.on_failure[1] = ParserIndex::Index(2);
-        // Since lambda is the last member of the disjunction that defines expressions that we try,
-        // we accept or reject the expression if and only if lambda accepts or rejects.
-        expression.on_success[2] = AcceptingState;
-        expression.on_failure[2] = RejectingState;
-
-        // We define a parser that tries to parse a string that starts with an expression and rejects otherwise.
-        let expression = Rc::new(expression);
-        let eof = Rc::new(EndOfInputParser);
-        let mut parser = Brick::new("only_expression", vec![expression.clone(), eof.clone()]);
-        parser.on_success[0] = Index(1); // if we parse an expression, we'll look for end-of-input.
-        parser.on_failure[0] = RejectingState;
-        // eof is run if and only if an expression was successfully parsed: whether it accepts
-        // determines whether we accept the input.
-        parser.on_success[1] = AcceptingState;
-        parser.on_failure[1] = RejectingState;
-
-        assert!(parser.parse("lambda").is_ok());
-        assert!(parser.parse("disjunction").is_ok());
-        assert!(parser.parse("if").is_err());
-        assert!(parser.parse("else").is_err());
-        assert!(parser.parse("").is_err());
-        assert!(
-            parser
-                .parse("disjunction if disjunction else lambda")
-                .is_ok()
-        );
-        assert!(
-            parser
-                .parse("disjunction if disjunction else disjunction if disjunction else lambda")
-                .is_ok()
-        );
-        assert!(
-            parser
-                .parse(
-                    "disjunction if disjunction else disjunction if disjunction else disjunction"
-                )
-                .is_ok()
-        );
-        assert!(parser.parse("disjunction if disjunction else").is_err());
+#[test]
+fn test_left_recursion() {
+    fn show(tree: &AbstractSyntaxTree<&str>) -> String {
+        match tree {
+            Leaf(_, text, _) => text.to_string(),
+            Branch(_, children, _) if children.len() == 1 => show(&children[0]),
+            Branch(_, children, _) => {
+                let children: Vec<String> = children.iter().map(show).collect();
+                format!("({})", children.join(" "))
+            }
+            AbstractSyntaxTree::Ignore(_) => String::new(),
+        }
     }
 
-    #[test]
-    fn test_chain_of_operations() {
-        // This tests creates a parser that matches operations like 123, 123+456, and 123+456/789.
-        let parse_numbers = Rc::new(AllWordsFromAlphabet {
-            name: "numerics".to_string(),
-            alphabet: NUMERICS.clone(),
-            allow_empty_word: false,
+    let grammar = Grammar::from_peg(
+        r#"
+        difference = difference "-" number | number ;
+        number     = [0-9]+ ;
+        "#,
+    )
+    .unwrap();
+    let difference = grammar.get("difference").unwrap();
+    let (remaining, tree) = difference.parse("10-2-3").unwrap();
+    assert_eq!(remaining, "");
+    assert_eq!(show(&tree), "((10 - 2) - 3)");
+    assert_eq!(tree.span(), Span::new(0, 6));
+    assert_eq!(difference.parse("7").unwrap().1.span(), Span::new(0, 1));
+    assert!(difference.parse("-1").is_err());
+
+    // Indirect left recursion, through a second rule, on a hand-built grammar.
+    let table = MemoTable::new();
+    let mut grammar = Grammar::new();
+    let member = grammar.rule("member");
+    let primary = grammar.rule("primary");
+    let identifier = Rc::new(AllWordsFromAlphabet {
+        name: "identifier".into(),
+        alphabet: LOWERCASE_ENGLISH.clone(),
+        allow_empty_word: false,
+    });
+    let access = Rc::new(Brick::make_linear(
+        "access",
+        vec![
+            primary.clone(),
+            Rc::new(StringParser::new(".")),
+            identifier.clone(),
+        ],
+    ));
+    grammar.define(
+        "member",
+        Rc::new(Memoize::left_recursive(
+            Rc::new(Brick::make_disjunction("member", vec![access, identifier])),
+            &table,
+        )),
+    );
+    grammar.define("primary", member.clone());
+    let (remaining, tree) = member.parse("a.b.c;").unwrap();
+    assert_eq!(remaining, ";");
+    assert_eq!(show(&tree), "((a . b) . c)");
+
+    // The same grammar in the text format, where both rules are left-recursive.
+    let grammar = Grammar::from_peg(
+        r#"
+        member  = primary "." id | id ;
+        primary = member ;
+        id      = [a-z]+ ;
+        "#,
+    )
+    .unwrap();
+    let (remaining, tree) = grammar.get("member").unwrap().parse("a.b.c;").unwrap();
+    assert_eq!(remaining, ";");
+    assert_eq!(show(&tree), "((a . b) . c)");
+    let (remaining, tree) = grammar.get("primary").unwrap().parse("a.b").unwrap();
+    assert_eq!(remaining, "");
+    assert_eq!(show(&tree), "(a . b)");
+
+    // Mutual left recursion, and a left-recursive rule nested in another one at the same offset.
+    let grammar = Grammar::from_peg(
+        r#"
+        a     = b "x" | "a" ;
+        b     = a "y" | "b" ;
+        sum   = sum "+" call | call ;
+        call  = call "(" sum ")" | [0-9] ;
+        "#,
+    )
+    .unwrap();
+    let a = grammar.get("a").unwrap();
+    assert_eq!(a.parse("ayxyx!").unwrap().0, "!");
+    assert_eq!(a.parse("bxyx").unwrap().0, "");
+    assert_eq!(show(&a.parse("ayx").unwrap().1), "((a y) x)");
+    let (remaining, tree) = grammar.get("sum").unwrap().parse("1+2(3+4)(5)").unwrap();
+    assert_eq!(remaining, "");
+    assert_eq!(show(&tree), "(1 + ((2 ( (3 + 4) )) ( 5 )))");
+
+    // Each round of growing a seed reuses the previous seed, so that left recursion runs in linear time.
+    with_large_stack(|| {
+        let grammar =
+            Grammar::from_peg("sum = sum ~\"+\" number | number ; number = [0-9]+ ;").unwrap();
+        let sum = grammar.get("sum").unwrap();
+        assert_linear(|n| {
+            let input = vec!["1"; n].join("+");
+            assert_eq!(sum.parse(&input).unwrap().0, "");
         });
-        let operators: Rc<HashSet<char>> = Rc::new(HashSet::from_iter("+-*/".chars()));
-        let parse_operator = Rc::new(CharacterFromAlphabet {
-            name: "operator".to_string(),
-            alphabet: operators,
+    });
+}
+
+#[test]
+fn test_operator_precedence() {
+    fn show(tree: &AbstractSyntaxTree<&str>) -> String {
+        match tree {
+            Leaf(_, text, _) => text.to_string(),
+            Branch(_, children, _) => {
+                let children: Vec<String> = children.iter().map(show).collect();
+                format!("({})", children.join(" "))
+            }
+            AbstractSyntaxTree::Ignore(_) => String::new(),
+        }
+    }
+
+    let number = Rc::new(AllWordsFromAlphabet {
+        name: "number".into(),
+        alphabet: NUMERICS.clone(),
+        allow_empty_word: false,
+    });
+    let operator = |symbol: &str| Rc::new(StringParser::new(symbol));
+    let expression = OperatorPrecedence::new("expression", number.clone())
+        .infix(operator("+"), 1, Associativity::Left)
+        .infix(operator("-"), 1, Associativity::Left)
+        .infix(operator("*"), 2, Associativity::Left)
+        .infix(operator("^"), 4, Associativity::Right)
+        .prefix(operator("-"), 3)
+        .postfix(operator("!"), 5);
+
+    let parse = |input| {
+        let (remaining, tree) = expression.parse(input).unwrap();
+        assert_eq!(remaining, "");
+        show(&tree)
+    };
+    assert_eq!(parse("1+2*3"), "(1 + (2 * 3))");
+    assert_eq!(parse("1*2+3"), "((1 * 2) + 3)");
+    assert_eq!(parse("1-2-3"), "((1 - 2) - 3)");
+    assert_eq!(parse("2^3^4"), "(2 ^ (3 ^ 4))");
+    assert_eq!(parse("-2^2"), "(- (2 ^ 2))");
+    assert_eq!(parse("-2*3"), "((- 2) * 3)");
+    assert_eq!(parse("3!^2"), "((3 !) ^ 2)");
+    assert_eq!(parse("42"), "42");
+
+    let (_, tree) = expression.parse("1+2*3").unwrap();
+    assert_eq!(tree.span(), Span::new(0, 5));
+    match tree {
+        Branch(_, children, _) => assert_eq!(children[2].span(), Span::new(2, 5)),
+        _ => panic!("Expected an operation."),
+    }
+
+    let (remaining, _) = expression.parse("1+2)").unwrap();
+    assert_eq!(remaining, ")");
+    let error = expression.parse("1+").unwrap_err();
+    assert_eq!(error.offset, 2);
+    assert!(expression.parse("*1").is_err());
+
+    // Operators of the lowest binding power associate and take operands like the others.
+    let expression = OperatorPrecedence::new("expression", number)
+        .infix(operator("="), 0, Associativity::Right)
+        .infix(operator("+"), 1, Associativity::Left)
+        .prefix(operator("!"), 0);
+    let parse = |input| show(&expression.parse(input).unwrap().1);
+    assert_eq!(parse("1=2=3"), "(1 = (2 = 3))");
+    assert_eq!(parse("1=2+3=4"), "(1 = ((2 + 3) = 4))");
+    assert_eq!(parse("!1+2"), "(! (1 + 2))");
+}
+
+#[test]
+fn test_bounded_repetition() {
+    let digit = Rc::new(CharacterFromAlphabet::new("digit", NUMERICS.clone()));
+    let count = |result: ParseResult<AbstractSyntaxTree<&str>>| match result {
+        Ok((remaining, Branch(_, children, _))) => (remaining.to_string(), children.len()),
+        _ => panic!("Expected a branch."),
+    };
+
+    let two_to_four = Repetition::new("digits", digit.clone(), 2, Some(4), None);
+    assert!(two_to_four.parse("1").is_err());
+    assert_eq!(count(two_to_four.parse("12")), ("".to_string(), 2));
+    assert_eq!(count(two_to_four.parse("123456")), ("56".to_string(), 4));
+
+    let any = Repetition::new("digits", digit.clone(), 0, None, None);
+    assert_eq!(count(any.parse("")), ("".to_string(), 0));
+    assert_eq!(count(any.parse("1234x")), ("x".to_string(), 4));
+
+    // Separators are part of the output, and a trailing separator is left in the input.
+    let comma: Rc<dyn Parser> = Rc::new(StringParser::new(","));
+    let list = Repetition::new("list", digit.clone(), 1, None, Some(comma.clone()));
+    assert_eq!(count(list.parse("1,2,3")), ("".to_string(), 5));
+    assert_eq!(count(list.parse("1,2,")), (",".to_string(), 3));
+    assert!(list.parse(",1").is_err());
+    let pair = Repetition::new("pair", digit.clone(), 2, Some(2), Some(comma.clone()));
+    assert_eq!(count(pair.parse("1,2,3")), (",3".to_string(), 3));
+    assert!(pair.parse("1,").is_err());
+
+    // A parser that accepts without consuming input does not make the repetition loop forever.
+    let maybe_digit = Rc::new(Brick::maybe("maybe_digit", digit.clone()));
+    let loop_guard = Repetition::new("digits", maybe_digit, 0, None, None);
+    assert_eq!(count(loop_guard.parse("12x")), ("x".to_string(), 2));
+
+    let grammar = Grammar::from_peg(r#"digits = ("0" | "1"?)* ;"#).unwrap();
+    let (remaining, _) = grammar.get("digits").unwrap().parse("0110x").unwrap();
+    assert_eq!(remaining, "x");
+
+    // Bounds are counted, not unrolled.
+    let many = Repetition::new("digits", digit.clone(), 0, Some(2_000_000), None);
+    assert_eq!(count(many.parse("123x")), ("x".to_string(), 3));
+    let unbounded = Repetition::new("digits", digit.clone(), 1, Some(usize::MAX), None);
+    assert_eq!(count(unbounded.parse("12")), ("".to_string(), 2));
+
+    // Each element and separator is parsed once.
+    let counter = Rc::new(Counter {
+        parser: digit.clone(),
+        count: AtomicUsize::new(0),
+    });
+    let list = Repetition::new("list", counter.clone(), 1, None, Some(comma.clone()));
+    assert_eq!(count(list.parse("1,2,3")), ("".to_string(), 5));
+    assert_eq!(counter.count.load(Ordering::Relaxed), 3);
+
+    // Committed errors of the elements are propagated as they are.
+    let mut pair = Brick::make_linear(
+        "pair",
+        vec![
+            digit.clone(),
+            Rc::new(StringParser::new("=")),
+            digit.clone(),
+        ],
+    );
+    pair.on_success[1] = ParserIndex::Commit(2);
+    let pairs = Repetition::new("pairs", Rc::new(pair), 0, None, Some(comma));
+    assert_eq!(count(pairs.parse("1=2,3=4")), ("".to_string(), 3));
+    let error = pairs.parse("1=2,3=x").unwrap_err();
+    assert!(error.committed);
+    assert_eq!(error.innermost().offset, 6);
+}
+
+#[test]
+fn test_lookahead() {
+    let letters: Rc<dyn Parser> = Rc::new(AllWordsFromAlphabet {
+        name: "letters".into(),
+        alphabet: LOWERCASE_ENGLISH.clone(),
+        allow_empty_word: false,
+    });
+    let letter: Rc<dyn Parser> = Rc::new(CharacterFromAlphabet::new(
+        "letter",
+        LOWERCASE_ENGLISH.clone(),
+    ));
+    let space: Rc<dyn Parser> = Rc::new(StringParser::new(" "));
+
+    // Ignore consumes what it matched but leaves it out of the output.
+    let words = Brick::make_linear(
+        "words",
+        vec![
+            letters.clone(),
+            Rc::new(Ignore::new(space.clone())),
+            letters.clone(),
+        ],
+    );
+    let (remaining, tree) = words.parse("ab cd!").unwrap();
+    assert_eq!(remaining, "!");
+    match tree {
+        Branch(_, children, span) => {
+            assert_eq!(children.len(), 2);
+            assert_eq!(span, Span::new(0, 5));
+        }
+        _ => panic!("Expected a branch."),
+    }
+
+    // Lookahead checks its parser without consuming input.
+    let peek = Lookahead::new(space.clone());
+    assert_eq!(peek.parse(" a").unwrap().0, " a");
+    assert!(peek.parse("a").is_err());
+
+    // A keyword is only recognised at a word boundary.
+    let keyword = Brick::make_linear(
+        "keyword",
+        vec![
+            Rc::new(StringParser::new("if")),
+            Rc::new(NotFollowedBy::new(letter)),
+        ],
+    );
+    assert_eq!(keyword.parse("if x").unwrap().0, " x");
+    assert_eq!(keyword.parse("if").unwrap().0, "");
+    let error = keyword.parse("iffy").unwrap_err();
+    assert_eq!(error.innermost().offset, 2);
+
+    // A brick rejected because its last parser accepted reports that parser's tree, even ignored.
+    let mut word = Brick::make_linear(
+        "word",
+        vec![letters.clone(), Rc::new(Ignore::new(space.clone()))],
+    );
+    word.on_success[1] = RejectingState;
+    word.on_failure[0] = Index(1);
+    // With "ab ", the tree of the letters comes before; with " ", the space is the only result.
+    for (input, span) in [("ab ", Span::new(2, 3)), (" ", Span::new(0, 1))] {
+        let error = word.parse(input).unwrap_err();
+        match error.propagation {
+            crate::PropagatedParseError::BecauseSubparserAccepted(_, tree) => {
+                assert_eq!(*tree, AbstractSyntaxTree::Ignore(span));
+            }
+            _ => panic!("Expected a rejection because of an accepted parser."),
+        }
+    }
+}
+
+#[test]
+fn test_commit() {
+    let disjunction = Rc::new(StringParser::new("disjunction"));
+    let lambda = Rc::new(StringParser::new("lambda"));
+    let mut chain = Brick::make_linear(
+        "ternary_operator",
+        vec![
+            disjunction.clone(),
+            Rc::new(StringParser::new(" if ")),
+            disjunction.clone(),
+            Rc::new(StringParser::new(" else ")),
+            lambda.clone(),
+        ],
+    );
+    let expression = |chain: Rc<Brick>| {
+        Brick::make_linear(
+            "only_expression",
+            vec![
+                Rc::new(Brick::make_disjunction(
+                    "expression",
+                    vec![chain, disjunction.clone(), lambda.clone()],
+                )),
+                Rc::new(EndOfInputParser),
+            ],
+        )
+    };
+
+    // Without a commit, the chain falls back to "disjunction" and the error is about the end of input.
+    let error = expression(Rc::new(Brick::make_linear(
+        "ternary_operator",
+        chain.parsers().to_vec(),
+    )))
+    .parse("disjunction if lambda")
+    .unwrap_err();
+    assert!(!error.committed);
+    assert_eq!(error.innermost().offset, 11);
+
+    // Once " if " is parsed, the chain is the only possible alternative.
+    chain.on_success[1] = ParserIndex::Commit(2);
+    let parser = expression(Rc::new(chain));
+    assert!(
+        parser
+            .parse("disjunction if disjunction else lambda")
+            .is_ok()
+    );
+    assert!(parser.parse("disjunction").is_ok());
+    let error = parser.parse("disjunction if lambda").unwrap_err();
+    assert!(error.committed);
+    assert_eq!(error.innermost().offset, 15);
+    assert_eq!(error.innermost().parser_name, "disjunction");
+
+    let brick = BrickBuilder::new("keyword")
+        .state("if", Rc::new(StringParser::new("if ")))
+        .state("condition", disjunction.clone())
+        .on_success("if", Target::Commit("condition".to_string()))
+        .build()
+        .unwrap();
+    assert_eq!(brick.on_success[0], ParserIndex::Commit(1));
+    assert!(brick.parse("if lambda").unwrap_err().committed);
+    assert!(brick.to_dot().contains("label=\"commit\""));
+}
+
+#[test]
+fn test_rollback() {
+    let leaves = |tree: AbstractSyntaxTree<&str>| match tree {
+        Branch(_, children, span) => (
+            children
+                .iter()
+                .map(|child| match child {
+                    Leaf(_, text, _) => text.to_string(),
+                    _ => panic!("Expected a leaf."),
+                })
+                .collect::<Vec<String>>(),
+            span,
+        ),
+        _ => panic!("Expected a branch."),
+    };
+
+    // "x" then either "a" "b" or "ac": when "b" fails, the "a" that was consumed is given back.
+    let mut brick = Brick::new(
+        "prefix",
+        vec![
+            Rc::new(StringParser::new("x")),
+            Rc::new(StringParser::new("a")),
+            Rc::new(StringParser::new("b")),
+            Rc::new(StringParser::new("ac")),
+        ],
+    );
+    brick.on_success = vec![
+        ParserIndex::Checkpoint(1),
+        Index(2),
+        AcceptingState,
+        AcceptingState,
+    ];
+    brick.on_failure = vec![RejectingState, Index(3), Index(3), RejectingState];
+    assert!(brick.parse("xac").is_err());
+
+    brick.on_failure[2] = ParserIndex::Rollback(3);
+    assert!(brick.validate().is_ok());
+    let (remaining, tree) = brick.parse("xac!").unwrap();
+    assert_eq!(remaining, "!");
+    assert_eq!(
+        leaves(tree),
+        (vec!["x".to_string(), "ac".to_string()], Span::new(0, 3))
+    );
+    let (_, tree) = brick.parse("xab").unwrap();
+    assert_eq!(leaves(tree).0, vec!["x", "a", "b"]);
+
+    // Without a checkpoint, a rollback goes back to the beginning of the input.
+    let mut brick = Brick::make_linear(
+        "alternatives",
+        vec![
+            Rc::new(StringParser::new("a")),
+            Rc::new(StringParser::new("b")),
+            Rc::new(StringParser::new("ac")),
+        ],
+    );
+    brick.on_success[1] = AcceptingState;
+    brick.on_failure[1] = ParserIndex::Rollback(2);
+    let (_, tree) = brick.parse("ac").unwrap();
+    assert_eq!(leaves(tree).0, vec!["ac"]);
+}
+
+#[test]
+fn test_typed_output() {
+    #[derive(Debug, PartialEq)]
+    enum Expression {
+        Number(i64),
+        Sum(i64, i64),
+    }
+
+    let digits = Rc::new(AllWordsFromAlphabet {
+        name: "number".into(),
+        alphabet: NUMERICS.clone(),
+        allow_empty_word: false,
+    });
+    let number = digits
+        .clone()
+        .try_map(|tree: AbstractSyntaxTree<&str>| match tree {
+            Leaf(_, text, _) => text
+                .parse::<i64>()
+                .map_err(|_| "a number that fits in an i64"),
+            _ => Err("a number"),
         });
-        let parse_operation = Rc::new(Brick::make_separated(
-            "operation".to_string(),
-            parse_numbers.clone(),
-            parse_operator.clone(),
-            false,
-        ));
-        let eof = Rc::new(EndOfInputParser);
-        let parser =
-            Brick::make_linear("only_operation", vec![parse_operation.clone(), eof.clone()]);
-
-        assert!(parser.parse("").is_err());
-        assert!(parser.parse("+").is_err());
-        assert!(parser.parse("123").is_ok());
-        assert!(parser.parse("123+").is_err());
-        assert!(parser.parse("123+456").is_ok());
-        assert!(parser.parse("0/0").is_ok());
-        assert!(parser.parse("0/2*2").is_ok());
-        assert!(parser.parse("1").is_ok());
-        assert!(parser.parse("12+21+12/12-65").is_ok());
-        assert!(parser.parse("+12").is_err());
-        assert!(parser.parse("-12").is_err());
-        assert!(parser.parse("/").is_err());
+    assert_eq!(number.parse_typed("42!").unwrap(), ("!", 42));
+    let error = number.parse_typed("99999999999999999999").unwrap_err();
+    assert_eq!(error.offset, 0);
+    assert!(
+        error
+            .render("99999999999999999999")
+            .contains("expected \"a number that fits in an i64\", found \"9\"")
+    );
+
+    let sign = Rc::new(StringParser::new("-"))
+        .map(|_| -1)
+        .or(Rc::new(StringParser::new("+")).map(|_| 1));
+    let signed = sign.clone().then(number.clone()).map(|(s, n)| s * n);
+    assert_eq!(signed.parse_typed("-3").unwrap(), ("", -3));
+    assert_eq!(signed.parse_typed("+3").unwrap(), ("", 3));
+    let error = signed.parse_typed("*3").unwrap_err();
+    assert_eq!((error.offset, error.parser_name.as_str()), (0, "+"));
+    let error = signed.parse_typed("-x").unwrap_err();
+    assert_eq!(error.offset, 1);
+
+    let sum = number
+        .clone()
+        .then(signed.repeated())
+        .map(|(first, rest)| match rest[..] {
+            [] => Expression::Number(first),
+            [n] => Expression::Sum(first, n),
+            _ => Expression::Number(first + rest.iter().sum::<i64>()),
+        });
+    assert_eq!(sum.parse_typed("7").unwrap().1, Expression::Number(7));
+    assert_eq!(sum.parse_typed("1+2").unwrap().1, Expression::Sum(1, 2));
+    assert_eq!(sum.parse_typed("1-2+3").unwrap().1, Expression::Number(2));
+    assert_eq!(
+        sum.parse_typed("1+2+").unwrap(),
+        ("+", Expression::Sum(1, 2))
+    );
+    assert!(sum.parse_typed("+").is_err());
+}
+
+#[test]
+fn test_from_ast() {
+    let number = Rc::new(AllWordsFromAlphabet {
+        name: "number".into(),
+        alphabet: NUMERICS.clone(),
+        allow_empty_word: false,
+    });
+    let list = Brick::make_separated(
+        "list",
+        number.clone(),
+        Rc::new(Ignore::new(Rc::new(StringParser::new(",")))),
+        false,
+    );
+
+    let (_, tree) = number.parse("42").unwrap();
+    assert_eq!(i64::from_ast(&tree), Ok(42));
+    assert_eq!(String::from_ast(&tree.into_owned()), Ok("42".to_string()));
+    let (_, tree) = list.parse("1,2,3").unwrap();
+    assert_eq!(Vec::<u8>::from_ast(&tree), Ok(vec![1, 2, 3]));
+
+    let error = i64::from_ast(&tree).unwrap_err();
+    assert_eq!(error.message, "expected a leaf, found branch \"list\"");
+    assert_eq!(error.span, Span::new(0, 5));
+    let (_, tree) = list.parse("1,300").unwrap();
+    let error = Vec::<u8>::from_ast(&tree).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "cannot convert \"300\" to u8: number too large to fit in target type at 2..5"
+    );
+}
+
+#[test]
+fn test_visitor_and_fold() {
+    use crate::{Fold, Visit, Visitor};
+
+    let grammar = Grammar::from_peg(
+        r#"
+        sum     = product ("+" product)* ;
+        product = number ("*" number)* ;
+        number  = [0-9]+ ;
+        "#,
+    )
+    .unwrap();
+    let (_, tree) = grammar.get("sum").unwrap().parse("1*2+3").unwrap();
+
+    let numbers: Vec<&str> = tree
+        .descendants()
+        .filter(|node| node.tag() == Some("number"))
+        .flat_map(|node| node.leaves())
+        .map(|leaf| match leaf {
+            Leaf(_, text, _) => *text,
+            _ => unreachable!(),
+        })
+        .collect();
+    assert_eq!(numbers, vec!["1", "2", "3"]);
+
+    // Pre-order and post-order callbacks see the nodes in the expected order.
+    let order = RefCell::new(vec![]);
+    tree.walk(
+        &mut TagVisitor::new()
+            .on_enter("product", |node| {
+                order
+                    .borrow_mut()
+                    .push(format!("enter {}", node.span().start))
+            })
+            .on_leave("product", |node| {
+                order
+                    .borrow_mut()
+                    .push(format!("leave {}", node.span().start))
+            }),
+    );
+    assert_eq!(
+        order.into_inner(),
+        vec!["enter 0", "leave 0", "enter 4", "leave 4"]
+    );
+
+    struct Depth {
+        current: usize,
+        max: usize,
+    }
+    impl<L> Visitor<L> for Depth {
+        fn enter(&mut self, node: &AbstractSyntaxTree<L>) -> Visit {
+            self.current += 1;
+            self.max = self.max.max(self.current);
+            if node.tag() == Some("product") {
+                Visit::SkipChildren
+            } else {
+                Visit::Continue
+            }
+        }
+        fn leave(&mut self, _: &AbstractSyntaxTree<L>) {
+            self.current -= 1;
+        }
+    }
+    let mut depth = Depth { current: 0, max: 0 };
+    tree.walk(&mut depth);
+    assert_eq!((depth.current, depth.max), (0, 4));
+
+    // Branches with a single child are replaced by their child.
+    struct Flatten;
+    impl<L> Fold<L> for Flatten {
+        fn fold_branch(
+            &mut self,
+            tag: Symbol,
+            mut children: Vec<AbstractSyntaxTree<L>>,
+            span: Span,
+        ) -> AbstractSyntaxTree<L> {
+            if children.len() == 1 {
+                children.pop().unwrap()
+            } else {
+                Branch(tag, children.into(), span)
+            }
+        }
+    }
+    let before = tree.descendants().count();
+    let tree = tree.fold(&mut Flatten);
+    assert!(tree.descendants().count() < before);
+    assert!(tree.descendants().all(|node| node.children().len() != 1));
+    assert_eq!(tree.leaves().count(), 5);
+}
+
+#[test]
+fn test_query() {
+    let grammar = Grammar::from_peg(
+        r#"
+        program    = function+ ;
+        function   = ~"fn " identifier ~"(" arguments ~") " block ;
+        arguments  = (identifier (~", " identifier)*)? ;
+        block      = ~"{" (call ~";")* ~"}" ~" "? ;
+        call       = identifier ~"(" arguments ~")" ;
+        identifier = [a-z_]+ ;
+        "#,
+    )
+    .unwrap();
+    let source = "fn main() {print(x);exit();} fn add(a, b) {sum(a, b);} ";
+    let (remaining, tree) = grammar.get("program").unwrap().parse(source).unwrap();
+    assert_eq!(remaining, "");
+
+    let texts = |selector: &str| -> Vec<&str> {
+        Query::new(selector)
+            .unwrap()
+            .select(&tree)
+            .iter()
+            .map(|node| &source[node.span().range()])
+            .collect()
+    };
+    assert_eq!(texts("function > identifier"), vec!["main", "add"]);
+    assert_eq!(
+        texts("function identifier"),
+        vec![
+            "main", "print", "x", "exit", "add", "a", "b", "sum", "a", "b"
+        ]
+    );
+    assert_eq!(texts("call > identifier"), vec!["print", "exit", "sum"]);
+    assert_eq!(texts("function:nth(1) call arguments"), vec!["a, b"]);
+    assert_eq!(texts("program > *:nth(1) > identifier"), vec!["add"]);
+    assert_eq!(texts("function > *:nth(0)"), vec!["main", "add"]);
+    assert_eq!(texts("identifier[text^=\"ex\"]"), vec!["exit"]);
+    assert_eq!(texts("call[text*=\"a\"] > *:nth(0)"), vec!["sum"]);
+    assert_eq!(texts("function[text^=\"addab\"] > identifier"), vec!["add"]);
+    assert!(texts("block > identifier").is_empty());
+
+    let error = Query::new("function >").unwrap_err();
+    assert_eq!(error.offset, 10);
+    assert!(Query::new("call:first").is_err());
+    assert!(Query::new("identifier[text=\"a]").is_err());
+}
+
+#[test]
+fn test_serialization() {
+    let grammar = Grammar::from_peg(
+        r#"
+        list   = ~"(" item (~"," item)* ~")" ;
+        item   = [a-z]+ | string ;
+        string = ~"'" [a-z "\\]* ~"'" ;
+        "#,
+    )
+    .unwrap();
+    let source = "(ab,'c\"d')";
+    let (_, tree) = grammar.get("list").unwrap().parse(source).unwrap();
+    let tree = tree.into_owned();
+
+    let sexp = tree.to_sexp();
+    assert_eq!(
+        sexp,
+        r#"(list @0..10 (item @1..3 (item_word @1..3 "ab")) (list_repetition @3..9 (list_sequence @3..9 (item @4..9 (string @4..9 (string_word @5..8 "c\"d"))))))"#
+    );
+    assert_eq!(AbstractSyntaxTree::from_sexp(&sexp).unwrap(), tree);
+
+    let tree =
+        AbstractSyntaxTree::from_sexp(r#"(sum @0..3 ("a b" @0..1 "1") (@1..2) (number "2\n"))"#)
+            .unwrap();
+    assert_eq!(
+        tree,
+        Branch(
+            "sum".into(),
+            vec![
+                Leaf("a b".into(), "1".to_string(), Span::new(0, 1)),
+                AbstractSyntaxTree::Ignore(Span::new(1, 2)),
+                Leaf("number".into(), "2\n".to_string(), Span::default()),
+            ]
+            .into(),
+            Span::new(0, 3),
+        )
+    );
+    assert_eq!(
+        tree.to_sexp(),
+        r#"(sum @0..3 ("a b" @0..1 "1") (@1..2) (number @0..0 "2\n"))"#
+    );
+    let error = AbstractSyntaxTree::from_sexp("(sum (number \"1\")").unwrap_err();
+    assert_eq!(error.offset, 17);
+    assert!(AbstractSyntaxTree::from_sexp("(sum) (sum)").is_err());
+
+    // Deeply nested input is rejected rather than overflowing the stack.
+    let nested = |depth: usize| "(a ".repeat(depth) + &")".repeat(depth);
+    assert!(AbstractSyntaxTree::from_sexp(&nested(128)).is_ok());
+    let error = AbstractSyntaxTree::from_sexp(&nested(1_000_000)).unwrap_err();
+    assert_eq!(error.offset, 3 * 128);
+    assert!(error.message.contains("at most 128 levels deep"));
+
+    #[cfg(feature = "serde")]
+    {
+        let json = serde_json::to_string(&tree).unwrap();
+        assert_eq!(
+            json,
+            r#"{"tag":"sum","children":[{"tag":"a b","text":"1","span":{"start":0,"end":1}},{"span":{"start":1,"end":2}},{"tag":"number","text":"2\n","span":{"start":0,"end":0}}],"span":{"start":0,"end":3}}"#
+        );
+        let read: AbstractSyntaxTree = serde_json::from_str(&json).unwrap();
+        assert_eq!(read, tree);
+        let read: AbstractSyntaxTree =
+            serde_json::from_str(r#"{"tag":"sum","children":[{"tag":"n","text":"1"}]}"#).unwrap();
+        assert_eq!(read.children()[0].span(), Span::default());
+        assert!(serde_json::from_str::<AbstractSyntaxTree>(r#"{"text":"1"}"#).is_err());
+
+        // Trees that borrow from the input serialize the same way.
+        let (_, borrowed) = grammar.get("list").unwrap().parse(source).unwrap();
+        let read: AbstractSyntaxTree =
+            serde_json::from_str(&serde_json::to_string(&borrowed).unwrap()).unwrap();
+        assert_eq!(read, borrowed.into_owned());
+    }
+}
+
+#[test]
+fn test_lossless() {
+    let grammar = Grammar::from_peg(
+        r##"
+        program    = ~spacing statement* ;
+        statement  = identifier ~spacing ~";" ~spacing ;
+        identifier = [a-z]+ ;
+        spacing    = ([ \n] | comment)* ;
+        comment    = "#" [a-z ]* "\n" ;
+        "##,
+    )
+    .unwrap();
+    let source = "  # intro\nfoo ;\nbar;  # end\n";
+    let (remaining, tree) = grammar
+        .get("program")
+        .unwrap()
+        .parse_lossless(source)
+        .unwrap();
+    assert_eq!(remaining, "");
+    assert_eq!(tree.unparse(), source);
+    assert_eq!(tree.into_owned().unparse(), source);
+
+    // Trivia go to the next leaf, or to the enclosing branch when no leaf follows.
+    fn trivia(tree: &ConcreteSyntaxTree<&str>, found: &mut Vec<(String, String)>) {
+        match tree {
+            ConcreteSyntaxTree::Leaf(tag, _, _, leading) => {
+                if let Some(trivia) = leading {
+                    found.push((tag.to_string(), trivia.text.to_string()));
+                }
+            }
+            ConcreteSyntaxTree::Branch(tag, children, _, trailing) => {
+                children.iter().for_each(|child| trivia(child, found));
+                if let Some(trivia) = trailing {
+                    found.push((tag.to_string(), trivia.text.to_string()));
+                }
+            }
+            ConcreteSyntaxTree::Ignore(..) => {}
+        }
+    }
+    let (_, tree) = grammar
+        .get("program")
+        .unwrap()
+        .parse_lossless(source)
+        .unwrap();
+    let mut found = vec![];
+    trivia(&tree, &mut found);
+    assert_eq!(
+        found,
+        vec![
+            ("identifier".to_string(), "  # intro\n".to_string()),
+            ("statement".to_string(), " ;\n".to_string()),
+            ("statement".to_string(), ";  # end\n".to_string()),
+        ]
+    );
+    match tree {
+        ConcreteSyntaxTree::Branch(_, _, span, _) => {
+            assert_eq!(span, Span::new(0, source.len()))
+        }
+        _ => panic!("expected a branch"),
+    }
+
+    let spacing = grammar.get("spacing").unwrap();
+    let (_, tree) = Ignore::new(spacing).parse_lossless(" # a\n x").unwrap();
+    assert_eq!(tree.unparse(), " # a\n ");
+    assert!(matches!(tree, ConcreteSyntaxTree::Ignore(Some(_), _)));
+}
+
+#[test]
+fn test_symbols() {
+    let number = Symbol::new("number");
+    assert_eq!(number, Symbol::from("number".to_string()));
+    assert_ne!(number, Symbol::new("numbers"));
+    assert_eq!(number, "number");
+    assert!(Symbol::new("a") < Symbol::new("b"));
+    assert_eq!(format!("{number} {number:?}"), "number \"number\"");
+
+    // Trees share the symbols of the parsers that produced them, which their grammar interns.
+    let mut grammar =
+        Grammar::from_peg("sum = number (~\"+\" number)* ; number = [0-9]+ ;").unwrap();
+    let (_, tree) = grammar.get("sum").unwrap().parse("1+2").unwrap();
+    let leaves: Vec<_> = tree.leaves().collect();
+    assert!(std::ptr::eq(
+        leaves[0].symbol().unwrap().as_str(),
+        leaves[1].symbol().unwrap().as_str()
+    ));
+    assert!(leaves[0].symbol().unwrap().is_interned());
+    assert_eq!(leaves[0].symbol(), Some(&grammar.symbol("number")));
+    assert_eq!(leaves[0].symbol(), Some(&number));
+    assert_eq!(tree.symbol().map(Symbol::as_str), Some("sum"));
+
+    // Symbols of different interners, or not interned, are equal when their texts are.
+    let mut interner = Interner::new();
+    let sum = interner.intern("sum");
+    assert_eq!(sum, interner.intern("sum"));
+    assert_ne!(sum, interner.intern("product"));
+    assert_eq!(Some(&sum), tree.symbol());
+    assert_eq!(sum, Symbol::new("sum"));
+    assert!(interner.get("untrusted_product").is_none());
+    let symbols: HashSet<Symbol> = HashSet::from([grammar.symbol("sum"), Symbol::new("number")]);
+    assert!(symbols.contains(&sum) && symbols.contains(&leaves[1].symbol().unwrap().clone()));
+
+    // Looking up, querying and deserializing do not intern the texts they are given.
+    assert!(grammar.get("untrusted_product").is_none());
+    assert!(Query::new("untrusted_product > untrusted_factor").is_ok());
+    let tree =
+        AbstractSyntaxTree::from_sexp("(untrusted_quotient (untrusted_divisor \"2\"))").unwrap();
+    assert!(!tree.symbol().unwrap().is_interned());
+    assert_eq!(Symbol::new("untrusted_quotient"), *tree.symbol().unwrap());
+    assert_eq!(
+        Query::new("untrusted_quotient > untrusted_divisor")
+            .unwrap()
+            .select(&tree)
+            .len(),
+        1
+    );
+
+    let mut undefined = Grammar::<str>::new();
+    undefined.rule("b");
+    undefined.rule("a");
+    assert_eq!(undefined.undefined_rules(), vec!["a", "b"]);
+}
+
+#[test]
+fn test_shared_grammar() {
+    fn assert_send_sync<T: Send + Sync + ?Sized>() {}
+    assert_send_sync::<crate::sync::Grammar>();
+    assert_send_sync::<crate::sync::Brick>();
+    assert_send_sync::<AbstractSyntaxTree>();
+
+    // Left recursion memoizes through a table that is shared by the threads.
+    let grammar =
+        crate::sync::Grammar::from_peg("sum = sum ~\"+\" number | number ; number = [0-9]+ ;")
+            .unwrap();
+    let sum = grammar.get("sum").unwrap();
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = (1..=8)
+            .map(|n| {
+                let sum = sum.clone();
+                scope.spawn(move || {
+                    let input = vec!["1"; n * 50].join("+");
+                    let (remaining, tree) = sum.parse(&input).unwrap();
+                    assert_eq!(remaining, "");
+                    tree.leaves().count()
+                })
+            })
+            .collect();
+        for (n, handle) in (1..=8).zip(handles) {
+            assert_eq!(handle.join().unwrap(), n * 50);
+        }
+    });
+    let (_, tree) = sum.parse("1+2").unwrap();
+    let (_, other) = std::thread::scope(|scope| scope.spawn(|| sum.parse("1+2")).join())
+        .unwrap()
+        .unwrap();
+    assert_eq!(tree, other);
+}
+
+#[test]
+fn test_batch() {
+    use crate::sync::Grammar;
+    use crate::{Batch, BatchFileError};
+
+    let grammar =
+        Grammar::from_peg("config = entry* ; entry = [a-z]+ ~\"=\" [0-9]+ ~\"\\n\" ;").unwrap();
+    let inputs: Vec<String> = (0..100)
+        .map(|i| match i % 10 {
+            0 => format!("key={i}\nbad"),
+            _ => format!("key={i}\nother={}\n", i * 2),
+        })
+        .collect();
+    let inputs: Vec<&str> = inputs.iter().map(String::as_str).collect();
+    let batch = Batch::new(grammar.get("config").unwrap()).threads(4);
+    let (results, statistics) = batch.parse(&inputs);
+    assert_eq!(results.len(), 100);
+    for (i, result) in results.iter().enumerate() {
+        let (remaining, tree) = result.as_ref().unwrap();
+        let expected = grammar.get("config").unwrap().parse(inputs[i]).unwrap();
+        assert_eq!((*remaining, tree), (expected.0, &expected.1));
+    }
+    assert_eq!(statistics.inputs, 100);
+    // Trailing garbage is not accepted.
+    assert_eq!(
+        (statistics.accepted, statistics.partial, statistics.rejected),
+        (90, 10, 0)
+    );
+    assert_eq!(statistics.threads, 4);
+    assert_eq!(
+        statistics.units,
+        inputs.iter().map(|input| input.len()).sum::<usize>()
+    );
+
+    let directory = std::env::temp_dir().join(format!("batch_{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    let good = directory.join("good.conf");
+    let bad = directory.join("bad.conf");
+    let trailing = directory.join("trailing.conf");
+    std::fs::write(&good, "a=1\n").unwrap();
+    std::fs::write(&bad, "a=\n").unwrap();
+    std::fs::write(&trailing, "a=1\nb=2\n").unwrap();
+    let entry = Batch::new(grammar.get("entry").unwrap()).threads(8);
+    let paths = [good, bad, directory.join("missing.conf"), trailing];
+    let (results, statistics) = entry.parse_files(&paths);
+    std::fs::remove_dir_all(&directory).unwrap();
+    assert_eq!(results[0].as_ref().unwrap().0, "");
+    assert!(matches!(results[1], Err(BatchFileError::Parse(_))));
+    assert!(matches!(results[2], Err(BatchFileError::Io(_))));
+    assert_eq!(results[3].as_ref().unwrap().0, "b=2\n");
+    assert_eq!(
+        (
+            statistics.accepted,
+            statistics.partial,
+            statistics.rejected,
+            statistics.unreadable
+        ),
+        (1, 1, 1, 1)
+    );
+    assert_eq!((statistics.units, statistics.threads), (15, 4));
+
+    // Memoized grammars keep the results of each thread apart.
+    let grammar = Grammar::from_peg(
+        "sum = sum ~\"+\" product | product ; product = product ~\"*\" [0-9] | [0-9] ;",
+    )
+    .unwrap();
+    let sum = grammar.get("sum").unwrap();
+    let inputs: Vec<String> = (1..=64).map(|n| vec!["1*2"; n].join("+")).collect();
+    let inputs: Vec<&str> = inputs.iter().map(String::as_str).collect();
+    let (results, statistics) = Batch::new(sum.clone()).threads(8).parse(&inputs);
+    assert_eq!(statistics.accepted, 64);
+    for (input, result) in inputs.iter().zip(results) {
+        let (remaining, tree) = result.unwrap();
+        assert_eq!((remaining, tree), sum.parse(input).unwrap());
     }
 }
@@ -0,0 +1,285 @@
+use crate::PropagatedParseError::Atomic;
+use crate::utils::describe_first_item;
+use crate::{AbstractSyntaxTree, Input, ParseError, ParseResult, Parser};
+use std::rc::Rc;
+use std::sync::Arc;
+
+/// A parser whose output is a value of type `Output` rather than an `AbstractSyntaxTree`.
+///
+/// Parsers in an `Rc` or an `Arc`, as they are in bricks, are typed parsers whose output is their tree,
+/// so that bricks and atoms can be turned into typed parsers with `map` and `try_map`, and then
+/// combined with `then`, `or` and `repeated`:
+///
+/// ```
+/// # use parser::{AbstractSyntaxTree, AllWordsFromAlphabet, NUMERICS, TypedParser};
+/// # use std::rc::Rc;
+/// let number = Rc::new(AllWordsFromAlphabet {
+///     name: "number".into(),
+///     alphabet: NUMERICS.clone(),
+///     allow_empty_word: false,
+/// })
+/// .try_map(|tree: AbstractSyntaxTree<&str>| match tree {
+///     AbstractSyntaxTree::Leaf(_, text, _) => text.parse::<i64>().map_err(|e| e.to_string()),
+///     _ => Err("a number".to_string()),
+/// });
+/// assert_eq!(number.parse_typed("42").unwrap(), ("", 42));
+/// ```
+pub trait TypedParser<I: Input + ?Sized = str> {
+    /// The output may borrow from the input, like the leaves of an `AbstractSyntaxTree` do.
+    type Output<'a>
+    where
+        I: 'a;
+
+    /// Parses a prefix of `input`, which starts at unit `offset` of the outermost input, see `Parser::parse_at`.
+    fn parse_typed_at<'a>(
+        &self,
+        input: &'a I,
+        offset: usize,
+    ) -> ParseResult<'a, Self::Output<'a>, I>;
+
+    fn parse_typed<'a>(&self, input: &'a I) -> ParseResult<'a, Self::Output<'a>, I> {
+        self.parse_typed_at(input, 0)
+    }
+
+    /// Converts the output of the parser with `function`.
+    fn map<O, F>(self, function: F) -> Map<Self, F>
+    where
+        Self: Sized,
+        F: for<'a> Fn(Self::Output<'a>) -> O,
+    {
+        Map {
+            parser: self,
+            function,
+        }
+    }
+
+    /// Converts the output of the parser with `function`, which may reject it: the parser then
+    /// fails at the offset where it started, and the error of `function` is reported as what was
+    /// expected there.
+    fn try_map<O, E, F>(self, function: F) -> TryMap<Self, F>
+    where
+        Self: Sized,
+        E: ToString,
+        F: for<'a> Fn(Self::Output<'a>) -> Result<O, E>,
+    {
+        TryMap {
+            parser: self,
+            function,
+        }
+    }
+
+    /// Applies the parser and then `next`, outputting both of their outputs.
+    fn then<Q: TypedParser<I>>(self, next: Q) -> Then<Self, Q>
+    where
+        Self: Sized,
+    {
+        Then {
+            first: self,
+            second: next,
+        }
+    }
+
+    /// Applies the parser, or `other` if the parser fails, like a disjunction brick. Committed
+    /// errors are not recovered from (see `ParseError::committed`); when both parsers fail, the
+    /// error of `other` is returned. The input type must be `'static`, as `str` is.
+    fn or<Q>(self, other: Q) -> Or<Self, Q>
+    where
+        Self: Sized,
+        I: 'static,
+        Q: for<'a> TypedParser<I, Output<'a> = Self::Output<'a>>,
+    {
+        Or {
+            first: self,
+            second: other,
+        }
+    }
+
+    /// Applies the parser as many times as possible, outputting the outputs in order. Like
+    /// `Repetition`, it stops when the parser fails or accepts without consuming input, and
+    /// propagates committed errors.
+    fn repeated(self) -> Repeated<Self>
+    where
+        Self: Sized,
+    {
+        Repeated { parser: self }
+    }
+}
+
+impl<I: Input + ?Sized, P: Parser<I> + ?Sized> TypedParser<I> for Rc<P> {
+    type Output<'a>
+        = AbstractSyntaxTree<&'a I>
+    where
+        I: 'a;
+
+    fn parse_typed_at<'a>(
+        &self,
+        input: &'a I,
+        offset: usize,
+    ) -> ParseResult<'a, Self::Output<'a>, I> {
+        self.parse_at(input, offset)
+    }
+}
+
+impl<I: Input + ?Sized, P: Parser<I> + ?Sized> TypedParser<I> for Arc<P> {
+    type Output<'a>
+        = AbstractSyntaxTree<&'a I>
+    where
+        I: 'a;
+
+    fn parse_typed_at<'a>(
+        &self,
+        input: &'a I,
+        offset: usize,
+    ) -> ParseResult<'a, Self::Output<'a>, I> {
+        self.parse_at(input, offset)
+    }
+}
+
+/// The parser returned by `TypedParser::map`.
+#[derive(Clone)]
+pub struct Map<P, F> {
+    parser: P,
+    function: F,
+}
+
+impl<I: Input + ?Sized, P: TypedParser<I>, O, F> TypedParser<I> for Map<P, F>
+where
+    F: for<'a> Fn(P::Output<'a>) -> O,
+{
+    type Output<'a>
+        = O
+    where
+        I: 'a;
+
+    fn parse_typed_at<'a>(
+        &self,
+        input: &'a I,
+        offset: usize,
+    ) -> ParseResult<'a, Self::Output<'a>, I> {
+        let (remaining, output) = self.parser.parse_typed_at(input, offset)?;
+        Ok((remaining, (self.function)(output)))
+    }
+}
+
+/// The parser returned by `TypedParser::try_map`.
+#[derive(Clone)]
+pub struct TryMap<P, F> {
+    parser: P,
+    function: F,
+}
+
+impl<I: Input + ?Sized, P: TypedParser<I>, O, E: ToString, F> TypedParser<I> for TryMap<P, F>
+where
+    F: for<'a> Fn(P::Output<'a>) -> Result<O, E>,
+{
+    type Output<'a>
+        = O
+    where
+        I: 'a;
+
+    fn parse_typed_at<'a>(
+        &self,
+        input: &'a I,
+        offset: usize,
+    ) -> ParseResult<'a, Self::Output<'a>, I> {
+        let (remaining, output) = self.parser.parse_typed_at(input, offset)?;
+        match (self.function)(output) {
+            Ok(output) => Ok((remaining, output)),
+            Err(e) => {
+                let expected = e.to_string();
+                Err(ParseError::new(
+                    format!("Conversion at offset {offset} failed: {expected}."),
+                    "try_map",
+                    offset,
+                    Atomic(describe_first_item(input), expected),
+                ))
+            }
+        }
+    }
+}
+
+/// The parser returned by `TypedParser::then`.
+#[derive(Clone)]
+pub struct Then<P, Q> {
+    first: P,
+    second: Q,
+}
+
+impl<I: Input + ?Sized, P: TypedParser<I>, Q: TypedParser<I>> TypedParser<I> for Then<P, Q> {
+    type Output<'a>
+        = (P::Output<'a>, Q::Output<'a>)
+    where
+        I: 'a;
+
+    fn parse_typed_at<'a>(
+        &self,
+        input: &'a I,
+        offset: usize,
+    ) -> ParseResult<'a, Self::Output<'a>, I> {
+        let (remaining, first) = self.first.parse_typed_at(input, offset)?;
+        let position = offset + input.input_len() - remaining.input_len();
+        let (remaining, second) = self.second.parse_typed_at(remaining, position)?;
+        Ok((remaining, (first, second)))
+    }
+}
+
+/// The parser returned by `TypedParser::or`.
+#[derive(Clone)]
+pub struct Or<P, Q> {
+    first: P,
+    second: Q,
+}
+
+impl<I: Input + ?Sized + 'static, P: TypedParser<I>, Q> TypedParser<I> for Or<P, Q>
+where
+    Q: for<'a> TypedParser<I, Output<'a> = P::Output<'a>>,
+{
+    type Output<'a>
+        = P::Output<'a>
+    where
+        I: 'a;
+
+    fn parse_typed_at<'a>(
+        &self,
+        input: &'a I,
+        offset: usize,
+    ) -> ParseResult<'a, Self::Output<'a>, I> {
+        match self.first.parse_typed_at(input, offset) {
+            Err(e) if !e.committed => self.second.parse_typed_at(input, offset),
+            result => result,
+        }
+    }
+}
+
+/// The parser returned by `TypedParser::repeated`.
+#[derive(Clone)]
+pub struct Repeated<P> {
+    parser: P,
+}
+
+impl<I: Input + ?Sized, P: TypedParser<I>> TypedParser<I> for Repeated<P> {
+    type Output<'a>
+        = Vec<P::Output<'a>>
+    where
+        I: 'a;
+
+    fn parse_typed_at<'a>(
+        &self,
+        input: &'a I,
+        offset: usize,
+    ) -> ParseResult<'a, Self::Output<'a>, I> {
+        let mut remaining = input;
+        let mut outputs = vec![];
+        loop {
+            let position = offset + input.input_len() - remaining.input_len();
+            match self.parser.parse_typed_at(remaining, position) {
+                Ok((rest, output)) if rest.input_len() < remaining.input_len() => {
+                    remaining = rest;
+                    outputs.push(output);
+                }
+                Err(e) if e.committed => return Err(e),
+                _ => return Ok((remaining, outputs)),
+            }
+        }
+    }
+}
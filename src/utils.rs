@@ -1,53 +1,111 @@
-use crate::AbstractSyntaxTree;
+use crate::{AbstractSyntaxTree, Input};
 use std::fmt::{Debug, Display};
 
-#[derive(PartialEq)]
-pub enum PropagatedParseError {
+/// `L` is the type of the leaves of the trees that errors carry, see `AbstractSyntaxTree`.
+#[derive(PartialEq, Clone)]
+pub enum PropagatedParseError<L = String> {
     /// When an atom fails, its error has its propagation field set to `Atomic(actual, expected)`.
     Atomic(String, String),
 
     /// When a brick was sent to `ParserIndex::RejectingState` because the last parser it tried accepted,
     /// its error has its propagation field set to `BecauseSubparserAccepted(subparser_name, subparser_output)`.
-    BecauseSubparserAccepted(String, AbstractSyntaxTree),
+    BecauseSubparserAccepted(String, Box<AbstractSyntaxTree<L>>),
 
     /// When a brick was sent to `ParserIndex::RejectingState` because the last parser it tried failed,
     /// its error has its propagation field set to `BecauseSubparserRejected(subparser_error)`.
-    BecauseSubparserRejected(Box<ParseError>),
+    BecauseSubparserRejected(Box<ParseError<L>>),
 }
 
-#[derive(PartialEq)]
-pub struct ParseError {
+#[derive(PartialEq, Clone)]
+pub struct ParseError<L = String> {
     pub message: String,
     pub parser_name: String,
-    pub propagation: PropagatedParseError,
+
+    /// Offset in units of the input (bytes for `str`, elements for slices), in the input of the
+    /// outermost parser, at which the error occurred.
+    pub offset: usize,
+    pub propagation: PropagatedParseError<L>,
+
+    /// Whether the error occurred after a brick went through a `ParserIndex::Commit` transition.
+    /// Bricks do not follow `on_failure` on committed errors: they reject right away, so that
+    /// alternatives are not tried and the error is reported as is.
+    pub committed: bool,
 }
 
-impl ParseError {
+impl<L> ParseError<L> {
     pub fn new<T: ToString, U: ToString>(
         message: T,
         parser_name: U,
-        propagation: PropagatedParseError,
+        offset: usize,
+        propagation: PropagatedParseError<L>,
     ) -> Self {
         ParseError {
             message: message.to_string(),
             parser_name: parser_name.to_string(),
+            offset,
+            propagation,
+            committed: false,
+        }
+    }
+
+    /// Follows `BecauseSubparserRejected` links down to the error that started the failure.
+    pub fn innermost(&self) -> &ParseError<L> {
+        let mut error = self;
+        while let PropagatedParseError::BecauseSubparserRejected(inner) = &error.propagation {
+            error = inner;
+        }
+        error
+    }
+}
+
+impl<I: ToOwned + ?Sized> ParseError<&I> {
+    /// Converts the trees carried by the error to their owned form, see `AbstractSyntaxTree::into_owned`.
+    pub fn into_owned(self) -> ParseError<I::Owned> {
+        let propagation = match self.propagation {
+            PropagatedParseError::Atomic(actual, expected) => {
+                PropagatedParseError::Atomic(actual, expected)
+            }
+            PropagatedParseError::BecauseSubparserAccepted(name, tree) => {
+                PropagatedParseError::BecauseSubparserAccepted(name, Box::new(tree.into_owned()))
+            }
+            PropagatedParseError::BecauseSubparserRejected(inner) => {
+                PropagatedParseError::BecauseSubparserRejected(Box::new(inner.into_owned()))
+            }
+        };
+        ParseError {
+            message: self.message,
+            parser_name: self.parser_name,
+            offset: self.offset,
             propagation,
+            committed: self.committed,
         }
     }
 }
 
-impl Debug for ParseError {
+impl<L> Debug for ParseError<L> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "ParseError: {}", self.message)
     }
 }
 
-impl Display for ParseError {
+impl<L> Display for ParseError<L> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.message)
     }
 }
 
-pub type ParseResult<'a, T> = Result<(&'a str, T), ParseError>;
-//                                             ^ Interpreted parsed string
-//                                    ^^^^^^^ Remaining output
+pub type ParseResult<'a, T, I = str> = Result<(&'a I, T), ParseError<&'a I>>;
+//                                                 ^ Interpreted parsed input
+//                                        ^^^^^ Remaining input
+
+/// Result of `Parser::parse_owned`, whose tree and error own their leaves.
+pub type OwnedParseResult<'a, I = str> =
+    Result<(&'a I, AbstractSyntaxTree<<I as ToOwned>::Owned>), ParseError<<I as ToOwned>::Owned>>;
+
+/// Describes the first item of `input` in error messages, or returns an empty string if `input` is empty.
+pub(crate) fn describe_first_item<I: Input + ?Sized>(input: &I) -> String {
+    input
+        .first_item()
+        .map(|(item, _)| I::describe_item(&item))
+        .unwrap_or_default()
+}
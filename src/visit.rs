@@ -0,0 +1,183 @@
+use crate::AbstractSyntaxTree::{Branch, Ignore, Leaf};
+use crate::{AbstractSyntaxTree, Span, Symbol};
+use std::collections::HashMap;
+
+impl<L> AbstractSyntaxTree<L> {
+    /// Tag of the node as a symbol, to compare tags without comparing their texts.
+    pub fn symbol(&self) -> Option<&Symbol> {
+        match self {
+            Leaf(tag, _, _) | Branch(tag, _, _) => Some(tag),
+            Ignore(_) => None,
+        }
+    }
+
+    /// Children of the node, which are empty unless the node is a branch.
+    pub fn children(&self) -> &[AbstractSyntaxTree<L>] {
+        match self {
+            Branch(_, children, _) => children,
+            Leaf(..) | Ignore(_) => &[],
+        }
+    }
+
+    /// Iterates over the node and all the nodes below it, depth first, parents before their children.
+    pub fn descendants(&self) -> Descendants<'_, L> {
+        Descendants { stack: vec![self] }
+    }
+
+    /// Iterates over the leaves below the node, from left to right.
+    pub fn leaves(&self) -> impl Iterator<Item = &AbstractSyntaxTree<L>> {
+        self.descendants().filter(|node| matches!(node, Leaf(..)))
+    }
+
+    /// Walks the tree depth first, calling `visitor.enter` on each node before its children and
+    /// `visitor.leave` after them.
+    pub fn walk<V: Visitor<L> + ?Sized>(&self, visitor: &mut V) {
+        if visitor.enter(self) == Visit::Continue {
+            for child in self.children() {
+                child.walk(visitor);
+            }
+        }
+        visitor.leave(self);
+    }
+
+    /// Rebuilds the tree bottom-up with `folder`: children are folded before their parent.
+    pub fn fold<F: Fold<L> + ?Sized>(self, folder: &mut F) -> AbstractSyntaxTree<L>
+    where
+        L: Clone,
+    {
+        match self {
+            Leaf(tag, leaf, span) => folder.fold_leaf(tag, leaf, span),
+            Branch(tag, children, span) => {
+                let children = children.iter().map(|c| c.clone().fold(folder)).collect();
+                folder.fold_branch(tag, children, span)
+            }
+            Ignore(span) => folder.fold_ignore(span),
+        }
+    }
+}
+
+/// Iterator returned by `AbstractSyntaxTree::descendants`.
+pub struct Descendants<'t, L> {
+    stack: Vec<&'t AbstractSyntaxTree<L>>,
+}
+
+impl<'t, L> Iterator for Descendants<'t, L> {
+    type Item = &'t AbstractSyntaxTree<L>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.stack.pop()?;
+        self.stack.extend(node.children().iter().rev());
+        Some(node)
+    }
+}
+
+/// What `AbstractSyntaxTree::walk` does after `Visitor::enter`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Visit {
+    Continue,
+    /// The children of the node are not visited; `Visitor::leave` is still called on the node.
+    SkipChildren,
+}
+
+/// Callbacks of `AbstractSyntaxTree::walk`, in pre-order (`enter`) and post-order (`leave`).
+pub trait Visitor<L> {
+    fn enter(&mut self, _node: &AbstractSyntaxTree<L>) -> Visit {
+        Visit::Continue
+    }
+
+    fn leave(&mut self, _node: &AbstractSyntaxTree<L>) {}
+}
+
+type Callback<'f, L> = Box<dyn FnMut(&AbstractSyntaxTree<L>) + 'f>;
+
+/// A visitor made of closures registered by tag, for short analyses:
+///
+/// ```
+/// # use parser::{AllWordsFromAlphabet, Brick, NUMERICS, Parser, StringParser, TagVisitor};
+/// # use std::rc::Rc;
+/// # let number = Rc::new(AllWordsFromAlphabet {
+/// #     name: "number".into(),
+/// #     alphabet: NUMERICS.clone(),
+/// #     allow_empty_word: false,
+/// # });
+/// # let sum = Brick::make_separated("sum", number, Rc::new(StringParser::new("+")), false);
+/// let (_, tree) = sum.parse("1+2+3").unwrap();
+/// let mut numbers = 0;
+/// tree.walk(&mut TagVisitor::new().on_enter("number", |_| numbers += 1));
+/// assert_eq!(numbers, 3);
+/// ```
+pub struct TagVisitor<'f, L = String> {
+    on_enter: HashMap<Symbol, Callback<'f, L>>,
+    on_leave: HashMap<Symbol, Callback<'f, L>>,
+}
+
+impl<'f, L> TagVisitor<'f, L> {
+    pub fn new() -> TagVisitor<'f, L> {
+        TagVisitor {
+            on_enter: HashMap::new(),
+            on_leave: HashMap::new(),
+        }
+    }
+
+    /// Calls `callback` on the nodes tagged `tag` before their children are visited.
+    pub fn on_enter<T: Into<Symbol>, F: FnMut(&AbstractSyntaxTree<L>) + 'f>(
+        mut self,
+        tag: T,
+        callback: F,
+    ) -> TagVisitor<'f, L> {
+        self.on_enter.insert(tag.into(), Box::new(callback));
+        self
+    }
+
+    /// Calls `callback` on the nodes tagged `tag` after their children are visited.
+    pub fn on_leave<T: Into<Symbol>, F: FnMut(&AbstractSyntaxTree<L>) + 'f>(
+        mut self,
+        tag: T,
+        callback: F,
+    ) -> TagVisitor<'f, L> {
+        self.on_leave.insert(tag.into(), Box::new(callback));
+        self
+    }
+}
+
+impl<L> Default for TagVisitor<'_, L> {
+    fn default() -> Self {
+        TagVisitor::new()
+    }
+}
+
+impl<L> Visitor<L> for TagVisitor<'_, L> {
+    fn enter(&mut self, node: &AbstractSyntaxTree<L>) -> Visit {
+        if let Some(callback) = node.symbol().and_then(|tag| self.on_enter.get_mut(tag)) {
+            callback(node);
+        }
+        Visit::Continue
+    }
+
+    fn leave(&mut self, node: &AbstractSyntaxTree<L>) {
+        if let Some(callback) = node.symbol().and_then(|tag| self.on_leave.get_mut(tag)) {
+            callback(node);
+        }
+    }
+}
+
+/// Transformation of a tree by `AbstractSyntaxTree::fold`. Each method rebuilds a node from its
+/// parts, the children of a branch being already folded; by default, nodes are rebuilt as they were.
+pub trait Fold<L> {
+    fn fold_leaf(&mut self, tag: Symbol, leaf: L, span: Span) -> AbstractSyntaxTree<L> {
+        Leaf(tag, leaf, span)
+    }
+
+    fn fold_branch(
+        &mut self,
+        tag: Symbol,
+        children: Vec<AbstractSyntaxTree<L>>,
+        span: Span,
+    ) -> AbstractSyntaxTree<L> {
+        Branch(tag, children.into(), span)
+    }
+
+    fn fold_ignore(&mut self, span: Span) -> AbstractSyntaxTree<L> {
+        Ignore(span)
+    }
+}